@@ -0,0 +1,1256 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::ChipError;
+
+// Where Octo programs are loaded and start running
+const BASE_ADDRESS: usize = 0x200;
+// Guards against macros that expand into themselves forever
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+// Keeps the recursion of :calc expressions from overflowing the stack
+const MAX_CALC_DEPTH: usize = 256;
+
+/// Assemble a program written in the syntax of [Octo] into bytes ready for
+/// [`Chip8::load_rom`](crate::Chip8::load_rom).
+///
+/// Supports the instructions of CHIP-8, Super-Chip and XO-CHIP, labels, `:const`,
+/// `:alias`, `:macro`, `:stringmode`, `:calc`, `:byte`, `:unpack`, `:next`,
+/// `:call`, `:org`, `loop`/`while`/`again`, `if ... then`,
+/// `if ... begin ... else ... end` and bare numbers as sprite data. The debugger
+/// directives `:breakpoint` and `:monitor` are ignored. As in Octo, the program
+/// starts running at the `main` label.
+///
+/// [Octo]: https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md
+pub fn assemble(source: &str) -> Result<Vec<u8>, ChipError> {
+    let mut assembler = Assembler::new(tokenize(source));
+    assembler.run()?;
+
+    Ok(assembler.rom)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> ChipError {
+        ChipError::InvalidSource {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+
+            // Strings are single tokens that can hold spaces and hashes
+            let (mut quoted, mut escaped) = (c == '"', false);
+            let mut end = line.len();
+            while let Some(&(column, c)) = chars.peek() {
+                match (quoted, escaped, c) {
+                    (true, false, '\\') => escaped = true,
+                    (true, false, '"') => quoted = false,
+                    (true, _, _) => escaped = false,
+                    (false, _, c) if c.is_whitespace() => {
+                        end = column;
+                        break;
+                    }
+                    _ => {}
+                }
+                chars.next();
+            }
+            tokens.push_back(Token {
+                text: line[start..end].to_string(),
+                line: line_index + 1,
+                column: line[..start].chars().count() + 1,
+            });
+        }
+    }
+
+    tokens
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// What a :stringmode expands into for one character of its alphabet
+struct StringMode {
+    value: usize,
+    body: Vec<Token>,
+}
+
+// The control flow blocks waiting for their closing keyword
+enum Block {
+    // The address jumped back to by `again` and the `while` jumps to patch
+    Loop { start: usize, exits: Vec<usize> },
+    // The jump over the body when the condition does not hold
+    If { jump: usize },
+    // The jump over the else body at the end of the if body
+    Else { jump: usize },
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // The lower 12 bits of an instruction
+    Nnn,
+    // The 16 bit operand of F000 NNNN
+    Long,
+    // The v0 and v1 loads of :unpack, with the nibble or None for :unpack long
+    Unpack(Option<u8>),
+}
+
+struct Fixup {
+    offset: usize,
+    kind: FixupKind,
+    label: Token,
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    // Numbers from :calc can be fractions, they are truncated when used
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    string_modes: HashMap<String, HashMap<char, StringMode>>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(Block, Token)>,
+    expansions: usize,
+    // Reported by errors at the end of the source
+    last: Token,
+}
+
+impl Assembler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        let last = tokens.back().cloned().unwrap_or(Token {
+            text: String::new(),
+            line: 1,
+            column: 1,
+        });
+
+        Assembler {
+            tokens,
+            rom: Vec::new(),
+            here: 0,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            string_modes: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+            last,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), ChipError> {
+        // Room for the jump to main, dropped when main comes first
+        self.emit(0x1000);
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+
+        if let Some((_, token)) = self.blocks.pop() {
+            return Err(token.error(format!("'{}' is never closed", token.text)));
+        }
+
+        match self.labels.get("main") {
+            Some(&main) => {
+                if self.rom.len() >= 2 && main != BASE_ADDRESS {
+                    self.patch_nnn(0, main, &self.last.clone())?;
+                }
+            }
+            None => return Err(self.last.error("the program has no main label".to_string())),
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = *self.labels.get(&fixup.label.text).ok_or_else(|| {
+                fixup
+                    .label
+                    .error(format!("undefined label '{}'", fixup.label.text))
+            })?;
+            match fixup.kind {
+                FixupKind::Nnn => self.patch_nnn(fixup.offset, address, &fixup.label)?,
+                FixupKind::Long => {
+                    self.rom[fixup.offset] = (address >> 8) as u8;
+                    self.rom[fixup.offset + 1] = address as u8;
+                }
+                FixupKind::Unpack(nibble) => {
+                    self.patch_unpack(fixup.offset, nibble, address, &fixup.label)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), ChipError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if name.text == "main" && self.here == 2 && self.labels.is_empty() {
+                    self.rom.clear();
+                    self.here = 0;
+                }
+                self.define_label(name, self.address())?;
+            }
+            ":next" => {
+                // The label points at the second byte of the next instruction
+                let name = self.name()?;
+                self.define_label(name, self.address() + 1)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let value = match (self.consts.get(&token.text), self.try_number(&token)) {
+                    (Some(&value), _) => value,
+                    (None, Some(number)) => number? as f64,
+                    (None, None) => {
+                        return Err(token.error(format!("expected a number, got '{}'", token.text)))
+                    }
+                };
+                self.consts.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calculated()?;
+                self.consts.insert(name.text, value);
+            }
+            ":byte" => {
+                let byte = match self.peek() {
+                    Some("{") => self.calculated()? as i64 as u8,
+                    _ => self.byte()? as u8,
+                };
+                self.emit_byte(byte);
+            }
+            ":call" => self.address_instruction(0x2000)?,
+            ":unpack" => {
+                let nibble = match self.peek() {
+                    Some("long") => {
+                        self.next()?;
+                        None
+                    }
+                    _ => Some(self.nibble()? as u8),
+                };
+                let target = self.next()?;
+                self.emit(0x6000);
+                self.emit(0x6100);
+                match self.number_or_const_opt(&target)? {
+                    Some(address) => {
+                        self.patch_unpack(self.here - 2, nibble, address as usize, &target)?
+                    }
+                    None => self.reference(target, FixupKind::Unpack(nibble))?,
+                }
+            }
+            ":stringmode" => self.define_string_mode()?,
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address_token = self.next()?;
+                let address = self.number_or_const(&address_token)? as usize;
+                if address < BASE_ADDRESS {
+                    return Err(address_token
+                        .error(format!("cannot place code before {:#05x}", BASE_ADDRESS)));
+                }
+                self.here = address - BASE_ADDRESS;
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)
+            }
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)
+            }
+            "jump" => self.address_instruction(0x1000)?,
+            "jump0" => self.address_instruction(0xB000)?,
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | reg_x(x) | reg_y(y) | n)
+            }
+            "bcd" => self.register_instruction(0xF033)?,
+            "saveflags" => self.register_instruction(0xF075)?,
+            "loadflags" => self.register_instruction(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                let range = match self.peek() {
+                    Some("-") => {
+                        self.next()?;
+                        Some(self.register()?)
+                    }
+                    _ => None,
+                };
+                let opcode = match (token.text.as_str(), range) {
+                    ("save", None) => 0xF055 | reg_x(x),
+                    ("save", Some(y)) => 0x5002 | reg_x(x) | reg_y(y),
+                    (_, None) => 0xF065 | reg_x(x),
+                    (_, Some(y)) => 0x5003 | reg_x(x) | reg_y(y),
+                };
+                self.emit(opcode)
+            }
+            "i" => self.index_statement()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match token.text.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_instruction(opcode)?
+            }
+            "if" => self.if_statement(token)?,
+            "else" => match self.blocks.pop() {
+                Some((Block::If { jump }, _)) => {
+                    let end_jump = self.here;
+                    self.emit(0x1000);
+                    self.patch_nnn(jump, self.address(), &token)?;
+                    self.blocks.push((Block::Else { jump: end_jump }, token));
+                }
+                _ => return Err(token.error("'else' without 'if ... begin'".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If { jump } | Block::Else { jump }, _)) => {
+                    self.patch_nnn(jump, self.address(), &token)?
+                }
+                _ => return Err(token.error("'end' without 'if ... begin'".to_string())),
+            },
+            "loop" => {
+                let start = self.address();
+                self.blocks.push((
+                    Block::Loop {
+                        start,
+                        exits: Vec::new(),
+                    },
+                    token,
+                ));
+            }
+            "while" => {
+                let (skip_if_true, _) = self.condition()?;
+                self.emit(skip_if_true);
+                let exit = self.here;
+                self.emit(0x1000);
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|(block, _)| matches!(block, Block::Loop { .. }))
+                {
+                    Some((Block::Loop { exits, .. }, _)) => exits.push(exit),
+                    _ => return Err(token.error("'while' outside of a loop".to_string())),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop { start, exits }, _)) => {
+                    self.emit(0x1000);
+                    self.patch_nnn(self.here - 2, start, &token)?;
+                    for exit in exits {
+                        self.patch_nnn(exit, self.address(), &token)?;
+                    }
+                }
+                _ => return Err(token.error("'again' without 'loop'".to_string())),
+            },
+            _ => {
+                if let Some(register) = self.try_register(&token) {
+                    return self.register_statement(register);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(token);
+                }
+                if self.string_modes.contains_key(&token.text) {
+                    return self.expand_string_mode(token);
+                }
+                if let Some(value) = self.try_number(&token) {
+                    let value = value?;
+                    return match value {
+                        -128..=255 => {
+                            self.emit_byte(value as u8);
+                            Ok(())
+                        }
+                        _ => Err(token.error(format!("{} does not fit in a byte", value))),
+                    };
+                }
+                if token.text.starts_with(':') || !is_name(&token.text) {
+                    return Err(token.error(format!("unexpected '{}'", token.text)));
+                }
+
+                // Any other name is a call to a subroutine
+                self.emit(0x2000);
+                self.reference(token, FixupKind::Nnn)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // vx := ..., vx += ... and the other register operations
+    fn register_statement(&mut self, x: u8) -> Result<(), ChipError> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+
+        let opcode = match (op.text.as_str(), rhs.text.as_str()) {
+            (":=", "delay") => 0xF007 | reg_x(x),
+            (":=", "key") => 0xF00A | reg_x(x),
+            (":=", "random") => {
+                let mask = self.byte()?;
+                0xC000 | reg_x(x) | mask
+            }
+            _ => match (op.text.as_str(), self.try_register(&rhs)) {
+                (":=", Some(y)) => 0x8000 | reg_x(x) | reg_y(y),
+                ("|=", Some(y)) => 0x8001 | reg_x(x) | reg_y(y),
+                ("&=", Some(y)) => 0x8002 | reg_x(x) | reg_y(y),
+                ("^=", Some(y)) => 0x8003 | reg_x(x) | reg_y(y),
+                ("+=", Some(y)) => 0x8004 | reg_x(x) | reg_y(y),
+                ("-=", Some(y)) => 0x8005 | reg_x(x) | reg_y(y),
+                (">>=", Some(y)) => 0x8006 | reg_x(x) | reg_y(y),
+                ("=-", Some(y)) => 0x8007 | reg_x(x) | reg_y(y),
+                ("<<=", Some(y)) => 0x800E | reg_x(x) | reg_y(y),
+                (":=", None) => 0x6000 | reg_x(x) | self.byte_value(&rhs)?,
+                ("+=", None) => 0x7000 | reg_x(x) | self.byte_value(&rhs)?,
+                ("-=", None) => {
+                    let value = self.byte_value(&rhs)?;
+                    0x7000 | reg_x(x) | (0x100 - value) & 0xFF
+                }
+                _ => {
+                    return Err(op.error(format!(
+                        "unexpected '{} {}' after a register",
+                        op.text, rhs.text
+                    )))
+                }
+            },
+        };
+        self.emit(opcode);
+
+        Ok(())
+    }
+
+    // i := ..., i += vx
+    fn index_statement(&mut self) -> Result<(), ChipError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {}
+            "+=" => return self.register_instruction(0xF01E),
+            _ => return Err(op.error(format!("unexpected '{}' after i", op.text))),
+        }
+
+        match self.peek() {
+            Some("hex") => {
+                self.next()?;
+                self.register_instruction(0xF029)
+            }
+            Some("bighex") => {
+                self.next()?;
+                self.register_instruction(0xF030)
+            }
+            Some("long") => {
+                self.next()?;
+                self.emit(0xF000);
+                let target = self.next()?;
+                match self.number_or_const_opt(&target)? {
+                    Some(address) => self.emit(address),
+                    None => {
+                        self.emit(0x0000);
+                        self.reference(target, FixupKind::Long)?
+                    }
+                }
+                Ok(())
+            }
+            _ => self.address_instruction(0xA000),
+        }
+    }
+
+    fn if_statement(&mut self, token: Token) -> Result<(), ChipError> {
+        let (skip_if_true, skip_if_false) = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            "then" => self.emit(skip_if_false),
+            "begin" => {
+                self.emit(skip_if_true);
+                let jump = self.here;
+                self.emit(0x1000);
+                self.blocks.push((Block::If { jump }, token));
+            }
+            _ => {
+                return Err(keyword.error(format!(
+                    "expected 'then' or 'begin', got '{}'",
+                    keyword.text
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    // The instructions that skip when the condition holds and when it does not
+    fn condition(&mut self) -> Result<(u16, u16), ChipError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text.as_str() {
+            "key" => return Ok((0xE09E | reg_x(x), 0xE0A1 | reg_x(x))),
+            "-key" => return Ok((0xE0A1 | reg_x(x), 0xE09E | reg_x(x))),
+            "==" | "!=" => {}
+            _ => return Err(op.error(format!("unsupported comparison '{}'", op.text))),
+        }
+
+        let rhs = self.next()?;
+        let (equal, not_equal) = match self.try_register(&rhs) {
+            Some(y) => (0x5000 | reg_x(x) | reg_y(y), 0x9000 | reg_x(x) | reg_y(y)),
+            None => {
+                let value = self.byte_value(&rhs)?;
+                (0x3000 | reg_x(x) | value, 0x4000 | reg_x(x) | value)
+            }
+        };
+
+        match op.text.as_str() {
+            "==" => Ok((equal, not_equal)),
+            _ => Ok((not_equal, equal)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), ChipError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            if !is_name(&token.text) {
+                return Err(token.error(format!("invalid macro argument '{}'", token.text)));
+            }
+            params.push(token.text);
+        }
+        let body = self.block()?;
+
+        self.macros.insert(name.text, Macro { params, body });
+
+        Ok(())
+    }
+
+    // The tokens up to the } closing a { that was just read
+    fn block(&mut self) -> Result<Vec<Token>, ChipError> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(body);
+            }
+            body.push(token);
+        }
+    }
+
+    fn count_expansion(&mut self, token: &Token) -> Result<(), ChipError> {
+        self.expansions += 1;
+        match self.expansions > MAX_MACRO_EXPANSIONS {
+            true => Err(token.error(format!("'{}' expands forever", token.text))),
+            false => Ok(()),
+        }
+    }
+
+    fn expand_macro(&mut self, token: Token) -> Result<(), ChipError> {
+        self.count_expansion(&token)?;
+
+        let params = self.macros[&token.text].params.len();
+        let mut args = HashMap::new();
+        for i in 0..params {
+            let arg = self.next()?;
+            args.insert(self.macros[&token.text].params[i].clone(), arg);
+        }
+
+        let body: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|t| args.get(&t.text).unwrap_or(t).clone())
+            .collect();
+        for t in body.into_iter().rev() {
+            self.tokens.push_front(t);
+        }
+
+        Ok(())
+    }
+
+    // Alphabets given to the same name add up, each character with its own body
+    fn define_string_mode(&mut self) -> Result<(), ChipError> {
+        let name = self.name()?;
+        let (_, alphabet) = self.string()?;
+        self.expect("{")?;
+        let body = self.block()?;
+
+        let modes = self.string_modes.entry(name.text).or_default();
+        for (value, c) in alphabet.chars().enumerate() {
+            let body = body.clone();
+            modes.insert(c, StringMode { value, body });
+        }
+
+        Ok(())
+    }
+
+    // Every character of the string expands into the body of its string mode,
+    // with CHAR, INDEX and VALUE replaced by its code, its position in the string
+    // and its position in the alphabet
+    fn expand_string_mode(&mut self, token: Token) -> Result<(), ChipError> {
+        self.count_expansion(&token)?;
+        let (string, text) = self.string()?;
+
+        let modes = &self.string_modes[&token.text];
+        let mut tokens = Vec::new();
+        for (index, c) in text.chars().enumerate() {
+            let mode = modes.get(&c).ok_or_else(|| {
+                string.error(format!(
+                    "'{}' is not in the alphabet of '{}'",
+                    c.escape_default(),
+                    token.text
+                ))
+            })?;
+            let values = [
+                ("CHAR", c as usize),
+                ("INDEX", index),
+                ("VALUE", mode.value),
+            ];
+            tokens.extend(mode.body.iter().map(|t| {
+                match values.iter().find(|(name, _)| *name == t.text) {
+                    Some((_, value)) => Token {
+                        text: value.to_string(),
+                        ..t.clone()
+                    },
+                    None => t.clone(),
+                }
+            }));
+        }
+        for t in tokens.into_iter().rev() {
+            self.tokens.push_front(t);
+        }
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: Token, address: usize) -> Result<(), ChipError> {
+        if self.labels.contains_key(&name.text) {
+            return Err(name.error(format!("the label '{}' is already defined", name.text)));
+        }
+        self.labels.insert(name.text, address);
+
+        Ok(())
+    }
+
+    // { expression } as in Octo, which evaluates the operators from right to left
+    // without precedence
+    fn calculated(&mut self) -> Result<f64, ChipError> {
+        self.expect("{")?;
+        let value = self.calc_expression(0)?;
+        self.expect("}")?;
+
+        Ok(value)
+    }
+
+    fn calc_expression(&mut self, depth: usize) -> Result<f64, ChipError> {
+        let lhs = self.calc_term(depth)?;
+        if matches!(self.peek(), Some(")" | "}") | None) {
+            return Ok(lhs);
+        }
+
+        let op = self.next()?;
+        let rhs = self.calc_expression(depth + 1)?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let value = match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as u8 as f64,
+            "<=" => (lhs <= rhs) as u8 as f64,
+            "==" => (lhs == rhs) as u8 as f64,
+            "!=" => (lhs != rhs) as u8 as f64,
+            ">=" => (lhs >= rhs) as u8 as f64,
+            ">" => (lhs > rhs) as u8 as f64,
+            _ => return Err(op.error(format!("unknown operator '{}'", op.text))),
+        };
+
+        Ok(value)
+    }
+
+    fn calc_term(&mut self, depth: usize) -> Result<f64, ChipError> {
+        let token = self.next()?;
+        if depth > MAX_CALC_DEPTH {
+            return Err(token.error("the expression is nested too deeply".to_string()));
+        }
+
+        let value = match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(depth + 1)?;
+                self.expect(")")?;
+                value
+            }
+            "HERE" => self.address() as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            "strlen" => self.string()?.1.chars().count() as f64,
+            "@" => {
+                let address = self.calc_term(depth + 1)? as usize;
+                let byte = address
+                    .checked_sub(BASE_ADDRESS)
+                    .and_then(|offset| self.rom.get(offset));
+                byte.copied().unwrap_or(0) as f64
+            }
+            "-" | "~" | "!" | "sin" | "cos" | "tan" | "exp" | "log" | "abs" | "sqrt" | "sign"
+            | "ceil" | "floor" => {
+                let value = self.calc_term(depth + 1)?;
+                match token.text.as_str() {
+                    "-" => -value,
+                    "~" => !(value as i64) as f64,
+                    "!" => (value == 0.0) as u8 as f64,
+                    "sin" => value.sin(),
+                    "cos" => value.cos(),
+                    "tan" => value.tan(),
+                    "exp" => value.exp(),
+                    "log" => value.ln(),
+                    "abs" => value.abs(),
+                    "sqrt" => value.sqrt(),
+                    "sign" => match value == 0.0 {
+                        true => 0.0,
+                        false => value.signum(),
+                    },
+                    "ceil" => value.ceil(),
+                    _ => value.floor(),
+                }
+            }
+            text => match (self.consts.get(text), self.labels.get(text)) {
+                (Some(&value), _) => value,
+                (None, Some(&address)) => address as f64,
+                (None, None) => match (self.try_number(&token), text.parse::<f64>()) {
+                    (Some(_), Ok(value)) => value,
+                    (Some(number), Err(_)) => number? as f64,
+                    (None, _) => {
+                        return Err(
+                            token.error(format!("'{}' is not a constant or a defined label", text))
+                        )
+                    }
+                },
+            },
+        };
+
+        Ok(value)
+    }
+
+    // The loads into v0 and v1 of :unpack, with the offset of the second one
+    fn patch_unpack(
+        &mut self,
+        offset: usize,
+        nibble: Option<u8>,
+        address: usize,
+        token: &Token,
+    ) -> Result<(), ChipError> {
+        let high = match nibble {
+            Some(nibble) if address <= 0xFFF => nibble << 4 | (address >> 8) as u8,
+            Some(_) => {
+                return Err(token.error(format!(
+                    "the address {:#x} does not fit in 12 bits, use ':unpack long'",
+                    address
+                )))
+            }
+            None => (address >> 8) as u8,
+        };
+        self.rom[offset - 1] = high;
+        self.rom[offset + 1] = address as u8;
+
+        Ok(())
+    }
+
+    // An instruction taking an address, either a number or a label
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), ChipError> {
+        let target = self.next()?;
+        match self.number_or_const_opt(&target)? {
+            Some(address) if address > 0xFFF => Err(target.error(format!(
+                "the address {:#x} does not fit in 12 bits",
+                address
+            ))),
+            Some(address) => {
+                self.emit(opcode | address);
+                Ok(())
+            }
+            None => {
+                self.emit(opcode);
+                self.reference(target, FixupKind::Nnn)
+            }
+        }
+    }
+
+    // An instruction taking the X register
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), ChipError> {
+        let x = self.register()?;
+        self.emit(opcode | reg_x(x));
+
+        Ok(())
+    }
+
+    fn reference(&mut self, label: Token, kind: FixupKind) -> Result<(), ChipError> {
+        if !is_name(&label.text) {
+            return Err(label.error(format!("expected a label, got '{}'", label.text)));
+        }
+        // The reference is in the 2 bytes that were just emitted
+        self.fixups.push(Fixup {
+            offset: self.here - 2,
+            kind,
+            label,
+        });
+
+        Ok(())
+    }
+
+    fn patch_nnn(&mut self, offset: usize, address: usize, token: &Token) -> Result<(), ChipError> {
+        if address > 0xFFF {
+            return Err(token.error(format!(
+                "the address {:#x} does not fit in 12 bits, use 'i := long'",
+                address
+            )));
+        }
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (address >> 8) as u8;
+        self.rom[offset + 1] = address as u8;
+
+        Ok(())
+    }
+
+    fn address(&self) -> usize {
+        BASE_ADDRESS + self.here
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.emit_byte((opcode >> 8) as u8);
+        self.emit_byte(opcode as u8);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        if self.rom.len() <= self.here {
+            self.rom.resize(self.here + 1, 0);
+        }
+        self.rom[self.here] = byte;
+        self.here += 1;
+    }
+
+    fn next(&mut self) -> Result<Token, ChipError> {
+        self.tokens
+            .pop_front()
+            .ok_or_else(|| self.last.error("unexpected end of the source".to_string()))
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), ChipError> {
+        let token = self.next()?;
+        match token.text == text {
+            true => Ok(()),
+            false => Err(token.error(format!("expected '{}', got '{}'", text, token.text))),
+        }
+    }
+
+    // A string in double quotes, with its token
+    fn string(&mut self) -> Result<(Token, String), ChipError> {
+        let token = self.next()?;
+        let mut chars = match token.text.strip_prefix('"') {
+            Some(rest) => rest.chars(),
+            None => return Err(token.error(format!("expected a string, got '{}'", token.text))),
+        };
+
+        let mut text = String::new();
+        loop {
+            let c = match chars.next() {
+                Some('"') if chars.as_str().is_empty() => return Ok((token, text)),
+                Some('"') | None => return Err(token.error("unterminated string".to_string())),
+                Some('\\') => match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('v') => '\x0B',
+                    Some('0') => '\0',
+                    Some(c @ ('\\' | '"')) => c,
+                    _ => return Err(token.error("invalid escape sequence".to_string())),
+                },
+                Some(c) => c,
+            };
+            text.push(c);
+        }
+    }
+
+    fn name(&mut self) -> Result<Token, ChipError> {
+        let token = self.next()?;
+        match is_name(&token.text) && self.try_register(&token).is_none() {
+            true => Ok(token),
+            false => Err(token.error(format!("invalid name '{}'", token.text))),
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, ChipError> {
+        let token = self.next()?;
+        self.try_register(&token)
+            .ok_or_else(|| token.error(format!("expected a register, got '{}'", token.text)))
+    }
+
+    fn try_register(&self, token: &Token) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Some(register);
+        }
+
+        let mut chars = token.text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(n), None) => n.to_digit(16).map(|n| n as u8),
+            _ => None,
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u16, ChipError> {
+        let token = self.next()?;
+        match self.number_or_const(&token)? {
+            n @ 0..=15 => Ok(n),
+            n => Err(token.error(format!("{} does not fit in 4 bits", n))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16, ChipError> {
+        let token = self.next()?;
+        self.byte_value(&token)
+    }
+
+    fn byte_value(&self, token: &Token) -> Result<u16, ChipError> {
+        match self.number_or_const(token)? {
+            n @ 0..=0xFF => Ok(n),
+            n if n >= 0xFF80 => Ok(n & 0xFF),
+            n => Err(token.error(format!("{} does not fit in a byte", n))),
+        }
+    }
+
+    // Negative numbers are stored as their two's complement
+    fn number_or_const(&self, token: &Token) -> Result<u16, ChipError> {
+        self.number_or_const_opt(token)?
+            .ok_or_else(|| token.error(format!("expected a number, got '{}'", token.text)))
+    }
+
+    fn number_or_const_opt(&self, token: &Token) -> Result<Option<u16>, ChipError> {
+        let value = match (self.consts.get(&token.text), self.try_number(token)) {
+            (Some(&value), _) => value as i64,
+            (None, Some(number)) => number?,
+            (None, None) => return Ok(None),
+        };
+
+        match value {
+            n @ -0x8000..=0xFFFF => Ok(Some(n as u16)),
+            n => Err(token.error(format!("{} does not fit in 16 bits", n))),
+        }
+    }
+
+    fn try_number(&self, token: &Token) -> Option<Result<i64, ChipError>> {
+        let (negative, text) = match token.text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, token.text.as_str()),
+        };
+        if !text.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let parsed = match (text.strip_prefix("0x"), text.strip_prefix("0b")) {
+            (Some(hex), _) => i64::from_str_radix(hex, 16),
+            (_, Some(binary)) => i64::from_str_radix(binary, 2),
+            _ => text.parse(),
+        };
+
+        Some(match parsed {
+            Ok(n) if negative => Ok(-n),
+            Ok(n) => Ok(n),
+            Err(_) => Err(token.error(format!("invalid number '{}'", token.text))),
+        })
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+fn reg_x(register: u8) -> u16 {
+    (register as u16) << 8
+}
+
+fn reg_y(register: u8) -> u16 {
+    (register as u16) << 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chip8, Config};
+
+    fn assert_error(source: &str, line: usize, column: usize) {
+        match assemble(source) {
+            Err(ChipError::InvalidSource {
+                line: l, column: c, ..
+            }) => assert_eq!((l, c), (line, column), "{}", source),
+            other => panic!("expected an error for {:?}, got {:?}", source, other),
+        }
+    }
+
+    #[test]
+    fn instructions() {
+        let rom = assemble(
+            ": main
+                clear
+                v0 := 5  v1 := v0  v1 += 2  v1 -= 1  v2 += v1
+                i := hex v0  sprite v0 v1 5
+                v3 := random 0xF0  v4 := key  delay := v4
+                save v3  load v2 - v5
+                jump main",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x00, 0xE0, 0x60, 0x05, 0x81, 0x00, 0x71, 0x02, 0x71, 0xFF, 0x82, 0x14, 0xF0, 0x29,
+                0xD0, 0x15, 0xC3, 0xF0, 0xF4, 0x0A, 0xF4, 0x15, 0xF3, 0x55, 0x52, 0x53, 0x12, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let rom = assemble(
+            ": sprite-data 0b11110000 0x90 -1
+             : main
+                i := sprite-data
+                i := long later
+                draw
+                :const ANSWER 42
+                :alias score v7
+                score := ANSWER
+             : draw
+                return
+             : later",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x12, 0x05, // jump main
+                0xF0, 0x90, 0xFF, // sprite-data
+                0xA2, 0x02, // i := sprite-data
+                0xF0, 0x00, 0x02, 0x11, // i := long later
+                0x22, 0x0F, // call draw
+                0x67, 0x2A, // score := ANSWER
+                0x00, 0xEE, // return
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let rom = assemble(
+            ": main
+             loop
+                v0 += 1
+                if v0 == 3 then v1 := 1
+                while v0 != 10
+                if v1 key begin
+                    v2 := 1
+                else
+                    v2 := 2
+                end
+             again",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x70, 0x01, // 0x200: v0 += 1
+                0x40, 0x03, // 0x202: skip unless v0 == 3
+                0x61, 0x01, // 0x204: v1 := 1
+                0x40, 0x0A, // 0x206: skip if v0 != 10
+                0x12, 0x16, // 0x208: exit the loop
+                0xE1, 0x9E, // 0x20A: skip if v1 key
+                0x12, 0x12, // 0x20C: jump to else
+                0x62, 0x01, // 0x20E: v2 := 1
+                0x12, 0x14, // 0x210: jump to end
+                0x62, 0x02, // 0x212: v2 := 2
+                0x12, 0x00, // 0x214: again
+            ]
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let rom = assemble(
+            ":macro add-twice reg amount { reg += amount reg += amount }
+             : main
+                add-twice v3 4
+                add-twice v4 1",
+        )
+        .unwrap();
+
+        assert_eq!(rom, [0x73, 0x04, 0x73, 0x04, 0x74, 0x01, 0x74, 0x01]);
+    }
+
+    #[test]
+    fn directives() {
+        let rom = assemble(
+            r##":stringmode text "ABC " { :byte { VALUE + 1 } }
+               :stringmode text "#" { :byte CHAR }
+               :calc SIZE { 2 * ( 3 + 1 ) }
+               :calc MIXED { 10 - 2 - 3 }
+             : main
+                :unpack 0xA data
+                :call draw
+                :next patched
+                v2 := 7
+                :breakpoint stop
+                :monitor data 4
+                jump main
+             : draw
+                i := data
+                return
+             : data
+                text "CAB #"
+                :byte SIZE
+                :byte { MIXED }
+                :byte { HERE - data }
+                :byte { @ patched }
+                :byte { strlen "a\"b" }
+                :unpack long far
+                :byte { sqrt 16 + 1 max 2 }
+                :byte { -1 }
+                :byte { 0.5 + 256.75 }
+                :byte { 1 < 2 }
+             : far"##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x60, 0xA2, 0x61, 0x0E, // :unpack 0xA data
+                0x22, 0x0A, // :call draw
+                0x62, 0x07, // v2 := 7
+                0x12, 0x00, // jump main
+                0xA2, 0x0E, // i := data
+                0x00, 0xEE, // return
+                0x03, 0x01, 0x02, 0x04, 0x23, // text "CAB #"
+                0x08, // SIZE is 2 * (3 + 1)
+                0x0B, // MIXED is 10 - (2 - 3)
+                0x07, // the offset of HERE
+                0x07, // the byte at patched
+                0x03, // the length of the string
+                0x60, 0x02, 0x61, 0x20, // :unpack long far
+                0x06, 0xFF, 0x01, 0x01,
+            ]
+        );
+    }
+
+    #[test]
+    fn runs() {
+        let rom = assemble(
+            ": main
+                v0 := 0
+                loop
+                    v0 += 1
+                    while v0 != 5
+                again
+                exit",
+        )
+        .unwrap();
+
+        let mut c8 = Chip8::new(Config::default());
+        c8.load_rom(&rom).unwrap();
+        while !c8.cpu.halted {
+            c8.step().unwrap();
+        }
+        assert_eq!(c8.cpu.v[0], 5);
+    }
+
+    #[test]
+    fn errors() {
+        assert_error(": main\n  v0 := 300", 2, 9);
+        assert_error(": main\n  jump nowhere", 2, 8);
+        assert_error(": main\n  v0 <= 3", 2, 6);
+        assert_error(": main\n  loop v0 += 1", 2, 3);
+        assert_error(": main\n  if v0 > 1 then clear", 2, 9);
+        assert_error(": main\n  sprite v0 v1 16", 2, 16);
+        assert_error(": main : main", 1, 10);
+        assert_error("clear", 1, 1);
+        assert_error(": main\n  v0 :=", 2, 6);
+        assert_error(": main\n  :byte { 1 ? 2 }", 2, 13);
+        assert_error(": main :calc X { nope }", 1, 18);
+        assert_error(": main\n  :unpack 0xA 0x1234", 2, 15);
+        assert_error(":stringmode s \"ab { }\n: main", 1, 15);
+        assert_error(
+            ":stringmode s \"ab\" { :byte VALUE }\n: main s \"abc\"",
+            2,
+            10,
+        );
+
+        let deep = format!(": main :byte {{ {}1 }}", "( ".repeat(1000));
+        assert!(matches!(
+            assemble(&deep),
+            Err(ChipError::InvalidSource { .. })
+        ));
+    }
+}
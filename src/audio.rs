@@ -0,0 +1,291 @@
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use crate::cpu::PATTERN_SIZE;
+use crate::{Chip8, Cpu};
+
+// The sound timer counts down at 60 Hz so the audio is rendered a frame at a time
+const FRAME_RATE: f64 = 60.0;
+const PATTERN_BITS: f64 = PATTERN_SIZE as f64 * 8.0;
+
+/// The shape of the tone played while the sound timer is active.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+}
+
+/// Renders the sound of the machine to mono PCM samples between -1.0 and 1.0.
+///
+/// Set it in [`Chip8::audio`] and the samples of every frame are rendered at the end
+/// of [`Chip8::tick`]. Once an XO-CHIP program has loaded an audio pattern it is
+/// played instead of the tone. Take them with [`Audio::drain`] and queue them in the audio
+/// output of the frontend.
+pub struct Audio {
+    /// The shape of the tone.
+    pub waveform: Waveform,
+    /// The pitch of the tone in Hz.
+    pub frequency: f64,
+    /// The loudness of the tone, from 0.0 to 1.0.
+    pub volume: f32,
+    /// How long the tone takes to fade in and out, which avoids clicks at its edges.
+    pub fade: Duration,
+    sample_rate: u32,
+    samples: Vec<f32>,
+    // Fraction of a sample left over by the previous frame
+    frame_remainder: f64,
+    // Position in the current period of the tone, from 0.0 to 1.0
+    phase: f64,
+    // The current volume of the fade, from 0.0 to 1.0
+    gain: f32,
+}
+
+impl Audio {
+    /// Create a renderer producing the amount of samples per second provided.
+    pub fn new(sample_rate: u32) -> Self {
+        Audio {
+            waveform: Waveform::default(),
+            frequency: 440.0,
+            volume: 0.25,
+            fade: Duration::from_millis(5),
+            sample_rate,
+            samples: Vec::new(),
+            frame_remainder: 0.0,
+            phase: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The samples rendered so far.
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    /// Take all the rendered samples.
+    pub fn drain(&mut self) -> std::vec::Drain<'_, f32> {
+        self.samples.drain(..)
+    }
+
+    /// Render the amount of samples provided, with the tone fading in when playing
+    /// and out when not.
+    pub fn render(&mut self, count: usize, playing: bool) {
+        let waveform = self.waveform;
+        let phase_step = self.frequency / self.sample_rate as f64;
+        self.render_with(count, playing, phase_step, |phase| {
+            waveform_value(waveform, phase)
+        });
+    }
+
+    /// Render the amount of samples provided, playing the 128 bits of an XO-CHIP
+    /// audio pattern in a loop at the rate given by the pitch register. A set bit is
+    /// played as the high level of the wave and a cleared one as the low level.
+    pub fn render_pattern(
+        &mut self,
+        count: usize,
+        playing: bool,
+        pattern: &[u8; PATTERN_SIZE],
+        pitch: u8,
+    ) {
+        // The phase goes through the whole pattern once per period
+        let phase_step = Audio::pattern_rate(pitch) / PATTERN_BITS / self.sample_rate as f64;
+        self.render_with(count, playing, phase_step, |phase| {
+            let bit = (phase * PATTERN_BITS) as usize;
+            match pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                true => 1.0,
+                false => -1.0,
+            }
+        });
+    }
+
+    /// The amount of bits of the audio pattern played per second for the value of
+    /// the XO-CHIP pitch register: 4000 Hz at the default pitch of 64, an octave
+    /// higher every 48 steps.
+    pub fn pattern_rate(pitch: u8) -> f64 {
+        4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+    }
+
+    // Renders the samples that last one 60 Hz frame, from the pattern of the CPU
+    // when one has been loaded
+    pub(crate) fn render_frame(&mut self, cpu: &Cpu) {
+        let samples = self.sample_rate as f64 / FRAME_RATE + self.frame_remainder;
+        self.frame_remainder = samples.fract();
+
+        let playing = cpu.timer_sound > 0;
+        match &cpu.pattern {
+            Some(pattern) => self.render_pattern(samples as usize, playing, pattern, cpu.pitch),
+            None => self.render(samples as usize, playing),
+        }
+    }
+
+    fn render_with(
+        &mut self,
+        count: usize,
+        playing: bool,
+        phase_step: f64,
+        value: impl Fn(f64) -> f32,
+    ) {
+        let fade_samples = (self.fade.as_secs_f64() * self.sample_rate as f64).max(1.0);
+        let gain_step = (1.0 / fade_samples) as f32;
+
+        self.samples.reserve(count);
+        for _ in 0..count {
+            self.gain = match playing {
+                true => (self.gain + gain_step).min(1.0),
+                false => (self.gain - gain_step).max(0.0),
+            };
+
+            let value = match self.gain > 0.0 {
+                true => value(self.phase),
+                false => 0.0,
+            };
+            self.samples.push(value * self.gain * self.volume);
+            self.phase = (self.phase + phase_step).fract();
+        }
+    }
+}
+
+fn waveform_value(waveform: Waveform, phase: f64) -> f32 {
+    let value = match waveform {
+        Waveform::Square => match phase < 0.5 {
+            true => 1.0,
+            false => -1.0,
+        },
+        Waveform::Sine => (phase * TAU).sin(),
+        Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+    };
+
+    value as f32
+}
+
+impl Chip8 {
+    // Renders the sound of the frame that just ended
+    pub(crate) fn render_audio_frame(&mut self) {
+        if let Some(audio) = &mut self.audio {
+            audio.render_frame(&self.cpu);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_per_frame() {
+        let mut c8 = Chip8::default();
+        c8.load_rom(&[0x12, 0x00]).unwrap();
+        c8.audio = Some(Audio::new(44100));
+        c8.cpu.timer_sound = 2;
+
+        for _ in 0..4 {
+            c8.tick().unwrap();
+        }
+        let audio = c8.audio.as_mut().unwrap();
+        // 735 samples per frame
+        assert_eq!(audio.samples().len(), 2940);
+        assert!(audio.samples()[..1470].iter().any(|&s| s != 0.0));
+        assert!(audio.samples()[1470 + 221..].iter().all(|&s| s == 0.0));
+
+        assert_eq!(audio.drain().len(), 2940);
+        assert!(audio.samples().is_empty());
+    }
+
+    #[test]
+    fn fractional_frames() {
+        let mut audio = Audio::new(1000);
+        for _ in 0..60 {
+            audio.render_frame(&Cpu::default());
+        }
+
+        assert_eq!(audio.samples().len(), 1000);
+    }
+
+    #[test]
+    fn fade() {
+        let mut audio = Audio::new(1000);
+        audio.volume = 1.0;
+        audio.frequency = 250.0;
+        audio.fade = Duration::from_millis(4);
+        audio.render(10, true);
+
+        // Square wave fading in over 4 samples
+        assert_eq!(
+            audio.samples(),
+            [0.25, 0.5, -0.75, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0]
+        );
+
+        audio.drain();
+        audio.render(6, false);
+        assert_eq!(audio.samples()[3..], [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn waveforms() {
+        let mut audio = Audio::new(8);
+        audio.volume = 1.0;
+        audio.frequency = 1.0;
+        audio.fade = Duration::ZERO;
+
+        audio.waveform = Waveform::Triangle;
+        audio.render(8, true);
+        assert_eq!(
+            audio.drain().collect::<Vec<_>>(),
+            [-1.0, -0.5, 0.0, 0.5, 1.0, 0.5, 0.0, -0.5]
+        );
+
+        audio.waveform = Waveform::Sine;
+        audio.render(4, true);
+        let sine: Vec<f32> = audio.drain().collect();
+        assert!(sine[0].abs() < 1e-6 && (sine[2] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pattern() {
+        assert_eq!(Audio::pattern_rate(64), 4000.0);
+        assert_eq!(Audio::pattern_rate(112), 8000.0);
+
+        let mut audio = Audio::new(4000);
+        audio.volume = 1.0;
+        audio.fade = Duration::ZERO;
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        audio.render_pattern(130, true, &pattern, 64);
+        // One bit per sample at 4000 Hz, looping after 128 bits
+        let samples = audio.samples();
+        assert_eq!(samples[..4], [1.0, -1.0, 1.0, -1.0]);
+        assert!(samples[4..128].iter().all(|&s| s == -1.0));
+        assert_eq!(samples[128..], [1.0, -1.0]);
+
+        // Every other bit is skipped an octave higher
+        let mut audio = Audio::new(4000);
+        audio.fade = Duration::ZERO;
+        audio.render_pattern(3, true, &pattern, 112);
+        assert_eq!(audio.samples(), [0.25, 0.25, -0.25]);
+    }
+
+    #[test]
+    fn xo_chip_pattern() {
+        let mut c8 = Chip8::new(crate::Config {
+            platform: crate::Platform::XoChip,
+            ..Default::default()
+        });
+        // 0x200: LD I, 0x20A ; 0x202: AUDIO ; 0x204: LD V0, 2 ; 0x206: LD ST, V0
+        // 0x208: JP 0x208 ; 0x20A: the pattern, all set
+        let mut rom = vec![0xA2, 0x0A, 0xF0, 0x02, 0x60, 0x02, 0xF0, 0x18, 0x12, 0x08];
+        rom.extend_from_slice(&[0xFF; 16]);
+        c8.load_rom(&rom).unwrap();
+        c8.audio = Some(Audio::new(6000));
+        c8.audio.as_mut().unwrap().fade = Duration::ZERO;
+
+        c8.tick().unwrap();
+        let audio = c8.audio.as_mut().unwrap();
+        assert_eq!(audio.samples().len(), 100);
+        assert!(audio.samples().iter().all(|&s| s == 0.25));
+    }
+}
@@ -0,0 +1,478 @@
+//! Terminal frontend that plays a ROM without a GUI, for example over SSH. Every
+//! character cell shows two pixels with the upper half block, so the 64x32 display
+//! fits in 64x16 cells.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use schip8::{Cartridge, Chip8, Config, Keymap, Palette, Platform, Quirks, Screen, StopReason};
+
+const USAGE: &str = "\
+Usage: schip8-tui [OPTIONS] <ROM>
+
+Plays a ROM or an Octo cartridge GIF in the terminal. Needs a terminal with 24-bit
+colors and stty. Cartridges run with their own options.
+
+Options:
+  --platform <NAME>  chip8 or xochip [default: chip8]
+  --quirks <NAME>    legacy, vip, chip48, schip10, schip11 or xochip [default: legacy]
+  --tick-rate <N>    Instructions per frame
+  --keymap <FILE>    Key overrides for the ROM, lines like `KeyW = 5`
+  -h, --help         Print this help
+
+Keys:
+  1 2 3 4    1 2 3 C
+  Q W E R    4 5 6 D
+  A S D F    7 8 9 E
+  Z X C V    A 0 B F
+
+  Space pauses, N runs one instruction while paused, Backspace resets and Esc quits.";
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+// Terminals only report key presses, repeated while the key is held down, so a key
+// is released once it hasn't been repeated for this many frames
+const HOLD_FRAMES: u8 = 8;
+// How many instructions the side panel lists from the PC
+const LISTING_LEN: usize = 12;
+
+#[derive(Debug)]
+struct Options {
+    rom: String,
+    platform: Platform,
+    quirks: Quirks,
+    tick_rate: Option<u32>,
+    keymap: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Key(usize),
+    Pause,
+    Step,
+    Reset,
+    Quit,
+}
+
+// Puts the terminal in raw mode on an alternate screen and restores it when dropped
+struct Terminal {
+    saved: String,
+}
+
+impl Terminal {
+    fn enter() -> Result<Terminal, String> {
+        let saved = stty(&["-g"])?.trim().to_string();
+        stty(&["raw", "-echo"])?;
+        let terminal = Terminal { saved };
+
+        let mut out = io::stdout().lock();
+        out.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J")
+            .and_then(|_| out.flush())
+            .map_err(|e| e.to_string())?;
+
+        Ok(terminal)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout().lock();
+        let _ = out.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = out.flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let result = parse_args(&args).and_then(|options| run(&options));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = fs::read(&options.rom).map_err(|e| format!("{}: {}", options.rom, e))?;
+
+    let (mut chip, palette, mut keymap) = match rom.starts_with(b"GIF") {
+        true => {
+            let cartridge = Cartridge::from_gif(&rom).map_err(|e| e.to_string())?;
+            (cartridge.chip, cartridge.palette, cartridge.keymap)
+        }
+        false => {
+            let mut chip = Chip8::new(Config {
+                platform: options.platform,
+                quirks: options.quirks,
+                ..Default::default()
+            });
+            chip.load_rom(&rom).map_err(|e| e.to_string())?;
+            (chip, Palette::default(), Keymap::default())
+        }
+    };
+    if let Some(tick_rate) = options.tick_rate {
+        chip.config.tick_rate = tick_rate;
+    }
+    if let Some(path) = &options.keymap {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        keymap
+            .apply_overrides(&text)
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    let _terminal = Terminal::enter()?;
+    let input = spawn_input();
+    let mut out = io::stdout().lock();
+
+    let mut held = [0u8; 16];
+    let mut paused = false;
+    let mut status = String::new();
+    let mut beeping = false;
+    let mut next_frame = Instant::now();
+    loop {
+        loop {
+            let bytes = match input.try_recv() {
+                Ok(bytes) => bytes,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            };
+            for action in parse_input(&bytes, &keymap) {
+                match action {
+                    Action::Key(key) => held[key] = HOLD_FRAMES,
+                    Action::Pause => paused = !paused,
+                    Action::Step if paused => {
+                        if let Err(e) = chip.step() {
+                            status = e.to_string();
+                        }
+                    }
+                    Action::Step => {}
+                    Action::Reset => {
+                        chip.reset();
+                        status.clear();
+                    }
+                    Action::Quit => return Ok(()),
+                }
+            }
+        }
+
+        chip.set_input(held.map(|frames| frames > 0));
+        if !paused {
+            match chip.tick() {
+                Ok(StopReason::Halted) => status = "Halted".to_string(),
+                Ok(_) => {}
+                Err(e) => {
+                    status = e.to_string();
+                    paused = true;
+                }
+            }
+        }
+        for frames in held.iter_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+
+        // The terminal bell stands in for the buzzer
+        let beep = chip.should_play_sound() && !beeping;
+        beeping = chip.should_play_sound();
+
+        let state = match paused {
+            true => "Paused",
+            false => "Running",
+        };
+        let status_line = format!(
+            "{}  {}  [Space] pause  [N] step  [Backspace] reset  [Esc] quit",
+            state, status
+        );
+        draw(&mut out, &chip, &palette, &status_line, beep).map_err(|e| e.to_string())?;
+
+        next_frame += FRAME;
+        let now = Instant::now();
+        match next_frame > now {
+            true => thread::sleep(next_frame - now),
+            // Running behind, don't try to catch up
+            false => next_frame = now,
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        platform: Platform::Chip8,
+        quirks: Quirks::default(),
+        tick_rate: None,
+        keymap: None,
+    };
+    let mut rom = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.replace(arg.clone()).is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--platform" => {
+                options.platform = match value.as_str() {
+                    "chip8" => Platform::Chip8,
+                    "xochip" => Platform::XoChip,
+                    _ => return Err(format!("unknown platform '{}'", value)),
+                }
+            }
+            "--quirks" => {
+                options.quirks = match value.as_str() {
+                    "legacy" => Quirks::default(),
+                    "vip" => Quirks::COSMAC_VIP,
+                    "chip48" => Quirks::CHIP_48,
+                    "schip10" => Quirks::SCHIP_1_0,
+                    "schip11" => Quirks::SCHIP_1_1,
+                    "xochip" => Quirks::XO_CHIP,
+                    _ => return Err(format!("unknown quirks '{}'", value)),
+                }
+            }
+            "--tick-rate" => {
+                let tick_rate = value
+                    .parse()
+                    .map_err(|_| format!("invalid tick rate '{}'", value))?;
+                options.tick_rate = Some(tick_rate);
+            }
+            "--keymap" => options.keymap = Some(value.clone()),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| format!("missing ROM\n\n{}", USAGE))?;
+
+    Ok(options)
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("stty: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "stty: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Reads the standard input on its own thread since reading blocks
+fn spawn_input() -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buffer = [0; 64];
+        while let Ok(len) = stdin.read(&mut buffer) {
+            if len == 0 || sender.send(buffer[..len].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// The bytes of a read from the terminal. A lone escape is the Esc key, followed by
+// more it starts the sequence of a key like the arrows, which are ignored. Digits
+// and letters are looked up in the keymap by the name of their key.
+fn parse_input(bytes: &[u8], keymap: &Keymap) -> Vec<Action> {
+    let mut actions = Vec::new();
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        let action = match byte.to_ascii_lowercase() {
+            0x1B => match bytes.next() {
+                None => Some(Action::Quit),
+                Some(_) => {
+                    // The sequence ends with a letter or a tilde
+                    for &b in bytes.by_ref() {
+                        if b.is_ascii_alphabetic() || b == b'~' {
+                            break;
+                        }
+                    }
+                    None
+                }
+            },
+            // Ctrl-C
+            0x03 => Some(Action::Quit),
+            b' ' => Some(Action::Pause),
+            b'n' => Some(Action::Step),
+            0x08 | 0x7F => Some(Action::Reset),
+            key => {
+                let name = match key {
+                    b'0'..=b'9' => format!("Digit{}", key as char),
+                    _ => format!("Key{}", key.to_ascii_uppercase() as char),
+                };
+                keymap.key(&name).map(|pad| Action::Key(pad as usize))
+            }
+        };
+        actions.extend(action);
+    }
+
+    actions
+}
+
+fn draw(
+    out: &mut impl Write,
+    chip: &Chip8,
+    palette: &Palette,
+    status: &str,
+    beep: bool,
+) -> io::Result<()> {
+    let rows = screen_rows(&chip.screen, palette);
+    let panel = side_panel(chip);
+
+    let mut frame = String::from("\x1b[H");
+    if beep {
+        frame.push('\x07');
+    }
+    for line in 0..rows.len().max(panel.len()) {
+        match rows.get(line) {
+            Some(row) => frame.push_str(row),
+            None => frame.push_str(&" ".repeat(chip.screen.width)),
+        }
+        frame.push_str("  ");
+        frame.push_str(panel.get(line).map_or("", |text| text));
+        frame.push_str("\x1b[K\r\n");
+    }
+    frame.push_str(status);
+    // Clear what's left of a larger previous frame
+    frame.push_str("\x1b[K\x1b[J");
+
+    out.write_all(frame.as_bytes())?;
+    out.flush()
+}
+
+// One line of text for every two rows of pixels: the upper half block is drawn
+// with the color of the top pixel over the color of the bottom one
+fn screen_rows(screen: &Screen, palette: &Palette) -> Vec<String> {
+    let mut rows = Vec::with_capacity(screen.height / 2);
+    for y in (0..screen.height).step_by(2) {
+        let mut row = String::new();
+        let mut previous = None;
+        for x in 0..screen.width {
+            let top = screen.get_pixel(x, y);
+            let bottom = match y + 1 < screen.height {
+                true => screen.get_pixel(x, y + 1),
+                false => 0,
+            };
+            // Only change the colors when they differ from the previous cell
+            if previous != Some((top, bottom)) {
+                let [_, tr, tg, tb] = palette.color(top).to_be_bytes();
+                let [_, br, bg, bb] = palette.color(bottom).to_be_bytes();
+                row.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                    tr, tg, tb, br, bg, bb
+                ));
+                previous = Some((top, bottom));
+            }
+            row.push('▀');
+        }
+        row.push_str("\x1b[0m");
+        rows.push(row);
+    }
+
+    rows
+}
+
+fn side_panel(chip: &Chip8) -> Vec<String> {
+    let cpu = &chip.cpu;
+    let mut panel = vec![
+        format!("PC {:04X}  I {:04X}  SP {:X}", cpu.pc, cpu.i, cpu.sp),
+        format!("DT {:02X}    ST {:02X}", cpu.timer_delay, cpu.timer_sound),
+    ];
+    for row in cpu.v.chunks(4).enumerate() {
+        let (start, values) = row;
+        let line: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X} {:02X}", start * 4 + i, v))
+            .collect();
+        panel.push(line.join("  "));
+    }
+    panel.push(String::new());
+
+    let listing = chip.disassemble(cpu.pc..cpu.pc + LISTING_LEN * 4);
+    for (i, instruction) in listing.iter().take(LISTING_LEN).enumerate() {
+        let marker = match i {
+            0 => '>',
+            _ => ' ',
+        };
+        panel.push(format!("{} {}", marker, instruction));
+    }
+
+    panel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            parse_input(b"qV x", &keymap),
+            [
+                Action::Key(0x4),
+                Action::Key(0xF),
+                Action::Pause,
+                Action::Key(0x0)
+            ]
+        );
+        // The up arrow is ignored, a lone escape quits
+        assert_eq!(parse_input(b"\x1b[A1", &keymap), [Action::Key(0x1)]);
+        assert_eq!(parse_input(b"\x1b", &keymap), [Action::Quit]);
+        assert_eq!(
+            parse_input(b"\x7fn", &keymap),
+            [Action::Reset, Action::Step]
+        );
+    }
+
+    #[test]
+    fn half_blocks() {
+        let mut screen = Screen::default();
+        screen.set_pixel(0, 1, 1);
+        screen.set_pixel(1, 1, 1);
+        let rows = screen_rows(&screen, &Palette::new(0x000000, 0xFFFFFF));
+
+        assert_eq!(rows.len(), 16);
+        assert_eq!(rows[0].matches('▀').count(), 64);
+        // The first two cells share their colors
+        assert!(rows[0].starts_with("\x1b[38;2;0;0;0m\x1b[48;2;255;255;255m▀▀\x1b[38;2;0;0;0m"));
+    }
+
+    #[test]
+    fn panel() {
+        let mut chip = Chip8::default();
+        chip.load_rom(&[0x60, 0x05, 0x12, 0x00]).unwrap();
+        chip.cpu.v[0xB] = 0x42;
+
+        let panel = side_panel(&chip);
+        assert_eq!(panel[0], "PC 0200  I 0000  SP 0");
+        assert_eq!(panel[4], "V8 00  V9 00  VA 00  VB 42");
+        assert_eq!(panel[7], "> 0x0200  6005      LD V0, 0x05");
+        assert_eq!(panel.len(), 7 + LISTING_LEN);
+    }
+}
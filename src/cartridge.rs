@@ -0,0 +1,293 @@
+use crate::gif::decode_gif;
+use crate::json::Json;
+use crate::{
+    assemble, Chip8, ChipError, Config, IndexIncrement, Keymap, Palette, Platform, Quirks,
+};
+
+// The options holding the colors of the color indexes 0 to 3
+const COLOR_OPTIONS: [&str; 4] = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+
+/// A program shared as an [Octo] cartridge: a GIF image whose pixels also hold the
+/// source code of the program and the options it runs with.
+///
+/// Every pixel holds 2 bits of data in the low bits of its color index, 4 pixels
+/// per byte starting from the high bits, going through the frames in order. The
+/// data starts with its length as 4 big-endian bytes, followed by JSON holding
+/// the `program` and its `options`.
+///
+/// [Octo]: https://github.com/JohnEarnest/Octo
+pub struct Cartridge {
+    /// The machine with the program loaded, configured with the options of the
+    /// cartridge and ready to run.
+    pub chip: Chip8,
+    /// The source code of the program, in the syntax of Octo.
+    pub source: String,
+    /// The colors of the options, for the color indexes 0 to 3.
+    pub palette: Palette,
+    /// The keyboard configuration of the options when the cartridge holds one,
+    /// otherwise the COSMAC VIP layout Octo uses by default.
+    pub keymap: Keymap,
+}
+
+impl Cartridge {
+    /// Read the cartridge and assemble its program.
+    ///
+    /// Programs run on XO-CHIP like in Octo. The tick rate, the quirks and the
+    /// colors are taken from the options, with the XO-CHIP quirks of Octo for the
+    /// ones that are missing. Like Octo, programs bigger than the `maxSize` option
+    /// are rejected. A keyboard configuration in the
+    /// `keys` option maps every key of the keypad, as a hex digit, to the list of
+    /// keyboard keys that press it, like `{"5": ["w", "ArrowUp"]}`.
+    pub fn from_gif(gif: &[u8]) -> Result<Self, ChipError> {
+        let images = decode_gif(gif).map_err(ChipError::InvalidCartridge)?;
+        let pixels: Vec<u8> = images
+            .iter()
+            .flat_map(|image| image.iter().copied())
+            .collect();
+        let bytes: Vec<u8> = pixels
+            .chunks_exact(4)
+            .map(|group| {
+                group
+                    .iter()
+                    .fold(0, |byte, &index| (byte << 2) | (index & 0x3))
+            })
+            .collect();
+
+        let len = match bytes.get(..4) {
+            Some(len) => u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize,
+            None => return Err(invalid("the image holds no data")),
+        };
+        let payload = bytes
+            .get(4..4 + len)
+            .ok_or_else(|| invalid("the data is longer than the image"))?;
+        // Octo writes every character as one byte
+        let text = match std::str::from_utf8(payload) {
+            Ok(text) => text.to_string(),
+            Err(_) => payload.iter().map(|&byte| byte as char).collect(),
+        };
+        let json = Json::parse(&text).map_err(ChipError::InvalidCartridge)?;
+
+        let source = json
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or_else(|| invalid("no program"))?
+            .to_string();
+        let options = json.get("options").unwrap_or(&Json::Null);
+        let rom = assemble(&source)?;
+
+        if let Some(max_size) = options.get("maxSize").and_then(Json::as_f64) {
+            if rom.len() as f64 > max_size {
+                return Err(ChipError::InvalidCartridge(format!(
+                    "the program takes {} bytes, more than the maxSize of {}",
+                    rom.len(),
+                    max_size
+                )));
+            }
+        }
+        let mut config = Config {
+            platform: Platform::XoChip,
+            quirks: options_quirks(options),
+            ..Default::default()
+        };
+        if let Some(tick_rate) = options.get("tickrate").and_then(Json::as_f64) {
+            config.tick_rate = tick_rate as u32;
+        }
+
+        let mut chip = Chip8::new(config);
+        chip.load_rom(&rom)?;
+
+        Ok(Cartridge {
+            chip,
+            source,
+            palette: options_palette(options),
+            keymap: options_keymap(options)?,
+        })
+    }
+}
+
+fn invalid(message: &str) -> ChipError {
+    ChipError::InvalidCartridge(message.to_string())
+}
+
+// The quirks of Octo, which are all off for XO-CHIP
+fn options_quirks(options: &Json) -> Quirks {
+    let flag = |name: &str| options.get(name).and_then(Json::as_bool);
+
+    let mut quirks = Quirks::XO_CHIP;
+    if let Some(shift) = flag("shiftQuirks") {
+        quirks.shift_uses_vy = !shift;
+    }
+    if let Some(load_store) = flag("loadStoreQuirks") {
+        quirks.load_store_index = match load_store {
+            true => IndexIncrement::Unchanged,
+            false => IndexIncrement::ByXPlusOne,
+        };
+    }
+    if let Some(jump) = flag("jumpQuirks") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(logic) = flag("logicQuirks") {
+        quirks.logic_resets_vf = logic;
+    }
+    if let Some(clip) = flag("clipQuirks") {
+        quirks.clip_sprites = clip;
+    }
+
+    quirks
+}
+
+fn options_palette(options: &Json) -> Palette {
+    let mut palette = Palette::default();
+    for (index, name) in COLOR_OPTIONS.iter().enumerate() {
+        let color = options
+            .get(name)
+            .and_then(Json::as_str)
+            .and_then(|color| color.strip_prefix('#'))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+        if let Some(color) = color {
+            palette.set_color(index as u8, color);
+        }
+    }
+    // The other indexes are only drawn with more than 2 planes
+    for index in 4..16 {
+        palette.set_color(index, palette.color(index % 4));
+    }
+
+    palette
+}
+
+// Octo names keys the way browsers do in the `key` of keyboard events
+fn options_keymap(options: &Json) -> Result<Keymap, ChipError> {
+    let Some(keys) = options.get("keys").and_then(Json::as_object) else {
+        return Ok(Keymap::default());
+    };
+
+    let mut keymap = Keymap::empty();
+    for (key, hosts) in keys {
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|&key| key <= 0xF)
+            .ok_or_else(|| invalid(&format!("invalid key '{}'", key)))?;
+        for host in hosts
+            .as_array()
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(Json::as_str)
+        {
+            let name = match host.as_bytes() {
+                [c] if c.is_ascii_digit() => format!("Digit{}", host),
+                [c] if c.is_ascii_alphabetic() => format!("Key{}", host.to_ascii_uppercase()),
+                b" " => "Space".to_string(),
+                _ => host.to_string(),
+            };
+            keymap.bind(&name, key)?;
+        }
+    }
+
+    Ok(keymap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GifRecorder, Screen};
+
+    // A cartridge with a label of vertical stripes drawn in the high bits of the
+    // color indexes
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+        let pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|&byte| [byte >> 6, byte >> 4, byte >> 2, byte].map(|bits| bits & 0x3))
+            .collect();
+
+        let mut recorder = GifRecorder::new(Palette::default(), 1);
+        let mut screen = Screen::default();
+        screen.set_hires(true);
+        for frame in pixels.chunks(128 * 64) {
+            screen.clear_screen();
+            for (i, &bits) in frame.iter().enumerate() {
+                let label = match i % 2 {
+                    0 => 0b0100,
+                    _ => 0b1000,
+                };
+                screen.set_pixel(i % 128, i / 128, label | bits);
+            }
+            recorder.capture(&screen);
+        }
+
+        recorder.to_gif()
+    }
+
+    #[test]
+    fn load() {
+        // Long enough to take two frames
+        let comment = "#".repeat(3000);
+        let json = format!(
+            r##"{{"program": "{}\n: main\n  v0 := 5\n  loop again",
+                "options": {{"tickrate": 500, "maxSize": 65024, "shiftQuirks": true,
+                "clipQuirks": true, "fillColor": "#FFCC00", "backgroundColor": "#996600",
+                "keys": {{"5": ["w", "ArrowUp"], "a": [" "]}}}}}}"##,
+            comment
+        );
+        let cart = Cartridge::from_gif(&cartridge(&json)).unwrap();
+
+        assert!(cart.source.ends_with("loop again"));
+        assert_eq!(cart.chip.memory[0x200..0x204], [0x60, 0x05, 0x12, 0x02]);
+        assert_eq!(cart.chip.config.tick_rate, 500);
+        assert_eq!(cart.chip.config.platform, Platform::XoChip);
+        assert!(!cart.chip.config.quirks.shift_uses_vy);
+        assert!(cart.chip.config.quirks.clip_sprites);
+        assert_eq!(
+            cart.chip.config.quirks.load_store_index,
+            IndexIncrement::ByXPlusOne
+        );
+
+        assert_eq!(cart.palette.color(0), 0x996600);
+        assert_eq!(cart.palette.color(5), 0xFFCC00);
+        assert_eq!(cart.palette.color(2), Palette::default().color(2));
+        assert_eq!(cart.keymap.key("KeyW"), Some(0x5));
+        assert_eq!(cart.keymap.key("ArrowUp"), Some(0x5));
+        assert_eq!(cart.keymap.key("Space"), Some(0xA));
+        assert_eq!(cart.keymap.key("KeyX"), None);
+    }
+
+    #[test]
+    fn octo_options() {
+        // The options Octo saves for a new program, with the settings it doesn't
+        // share with the interpreter
+        let json = r##"{"program": ": main\n  loop again",
+            "options": {"tickrate": 20, "fillColor": "#FFCC00", "fillColor2": "#FF6600",
+            "blendColor": "#662200", "backgroundColor": "#996600", "buzzColor": "#FFAA00",
+            "quietColor": "#000000", "shiftQuirks": false, "loadStoreQuirks": false,
+            "vfOrderQuirks": false, "clipQuirks": false, "vBlankQuirks": false,
+            "jumpQuirks": false, "screenRotation": 0, "maxSize": 3584,
+            "touchInputMode": "none", "logicQuirks": false, "fontStyle": "octo"}}"##;
+        let cart = Cartridge::from_gif(&cartridge(json)).unwrap();
+
+        assert_eq!(cart.chip.memory[0x200..0x202], [0x12, 0x00]);
+        assert_eq!(cart.chip.config.platform, Platform::XoChip);
+        assert_eq!(cart.chip.config.tick_rate, 20);
+        assert_eq!(cart.chip.config.quirks, Quirks::XO_CHIP);
+        let colors: Vec<u32> = (0..4).map(|index| cart.palette.color(index)).collect();
+        assert_eq!(colors, [0x996600, 0xFFCC00, 0xFF6600, 0x662200]);
+        assert_eq!(cart.keymap, Keymap::default());
+    }
+
+    #[test]
+    fn defaults() {
+        let cart = Cartridge::from_gif(&cartridge(r#"{"program": ": main\n  clear"}"#)).unwrap();
+        assert_eq!(cart.chip.config.platform, Platform::XoChip);
+        assert_eq!(cart.chip.config.quirks, Quirks::XO_CHIP);
+        assert_eq!(cart.keymap, Keymap::default());
+
+        let e = Cartridge::from_gif(&cartridge(r#"{"options": {}}"#));
+        assert!(matches!(e, Err(ChipError::InvalidCartridge(_))));
+        let e = Cartridge::from_gif(&cartridge(r#"{"program": ": main\n  bogus"}"#));
+        assert!(matches!(e, Err(ChipError::InvalidSource { .. })));
+        let json = r#"{"program": ": main\n  clear\n  loop again", "options": {"maxSize": 2}}"#;
+        let e = Cartridge::from_gif(&cartridge(json));
+        assert!(matches!(e, Err(ChipError::InvalidCartridge(m)) if m.contains("maxSize")));
+    }
+}
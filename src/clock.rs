@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use crate::{Chip8, ChipError, StopReason, Timing};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+// How often the timers count down
+const TIMER_HZ: u64 = 60;
+
+// A second in the unit used to keep time: nanoseconds multiplied by 60 so a frame
+// is a whole number of them
+const SECOND: u64 = NANOS_PER_SECOND * TIMER_HZ;
+
+// The time carried over between calls to advance: the progress towards the next
+// instruction, multiplied by the clock rate so whole numbers stay exact, and
+// towards the next 60 Hz frame.
+#[derive(Default)]
+pub(crate) struct Clock {
+    pub(crate) instruction_progress: f64,
+    pub(crate) frame_progress: u64,
+}
+
+impl Clock {
+    // The clock saved in a save state, None when the progress is past a whole step
+    pub(crate) fn restore(instruction_progress: f64, frame_progress: u64) -> Option<Clock> {
+        let valid = (0.0..SECOND as f64).contains(&instruction_progress)
+            && frame_progress < SECOND / TIMER_HZ;
+
+        valid.then_some(Clock {
+            instruction_progress,
+            frame_progress,
+        })
+    }
+}
+
+impl Chip8 {
+    /// Run the machine for the amount of real time that elapsed, for frontends that
+    /// don't refresh at exactly 60 Hz. The CPU runs at
+    /// [`Config::clock_rate`](crate::Config::clock_rate) instructions per second and
+    /// the timers count down at 60 Hz, with the leftover fractions carried over to
+    /// the next call. With [`Timing::CosmacVip`] a [`Chip8::tick`] runs for every frame.
+    ///
+    /// Returns how many 60 Hz frames were completed along with why the call returned:
+    /// [`StopReason::FrameComplete`] once all the elapsed time ran, or
+    /// [`StopReason::Halted`] when the ROM exited. A stop from the
+    /// [Debugger](crate::Debugger) ends the call early with its reason and the rest
+    /// of the elapsed time is dropped.
+    pub fn advance(&mut self, elapsed: Duration) -> Result<(u32, StopReason), ChipError> {
+        let clock_rate = self.clock_rate();
+        let mut remaining = (elapsed.as_nanos() as u64).saturating_mul(TIMER_HZ);
+        let mut frames = 0;
+
+        while remaining > 0 {
+            let chunk = remaining.min(SECOND / TIMER_HZ - self.clock.frame_progress);
+            remaining -= chunk;
+            self.clock.frame_progress += chunk;
+
+            if self.config.timing == Timing::CosmacVip {
+                // The VIP timing is locked to whole frames
+                if self.clock.frame_progress == SECOND / TIMER_HZ {
+                    self.clock.frame_progress = 0;
+                    match self.tick()? {
+                        StopReason::FrameComplete | StopReason::Halted => frames += 1,
+                        reason => return Ok((frames, reason)),
+                    }
+                }
+                continue;
+            }
+
+            self.clock.instruction_progress += chunk as f64 * clock_rate;
+            while self.clock.instruction_progress >= SECOND as f64 {
+                self.clock.instruction_progress -= SECOND as f64;
+                if self.cpu.halted {
+                    self.clock.instruction_progress = 0.0;
+                    break;
+                }
+                if let Some(reason) = self.debugger.check_before(&self.cpu) {
+                    return Ok((frames, reason));
+                }
+                self.step()?;
+                if let Some(reason) = self.debugger.check_after(&self.cpu) {
+                    return Ok((frames, reason));
+                }
+            }
+
+            if self.clock.frame_progress == SECOND / TIMER_HZ {
+                self.clock.frame_progress = 0;
+                self.end_frame();
+                frames += 1;
+            }
+        }
+
+        match self.cpu.halted {
+            true => Ok((frames, StopReason::Halted)),
+            false => Ok((frames, StopReason::FrameComplete)),
+        }
+    }
+
+    // Instructions per second, from the tick rate when not set
+    fn clock_rate(&self) -> f64 {
+        self.config
+            .clock_rate
+            .unwrap_or(self.config.tick_rate as f64 * TIMER_HZ as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    // 0x200: ADD V0, 1 ; 0x202: JP 0x200
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn fractional_clock() {
+        let mut c8 = Chip8::new(Config {
+            clock_rate: Some(150.0),
+            ..Default::default()
+        });
+        c8.load_rom(&COUNTER).unwrap();
+        c8.cpu.timer_delay = 100;
+
+        // 144 Hz: 150 / 144 instructions per call
+        let mut frames = 0;
+        for _ in 0..144 {
+            frames += c8.advance(Duration::from_nanos(6_944_444)).unwrap().0;
+        }
+        assert_eq!(frames, 59);
+        assert_eq!(c8.cpu.timer_delay, 41);
+        assert_eq!(c8.cpu.pc, 0x202);
+        assert_eq!(c8.cpu.v[0], 75);
+
+        // The leftovers add up to exactly one second
+        frames += c8.advance(Duration::from_nanos(64)).unwrap().0;
+        assert_eq!(frames, 60);
+        assert_eq!(c8.cpu.timer_delay, 40);
+        assert_eq!(c8.cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn dropped_frames() {
+        let mut c8 = Chip8::default();
+        c8.load_rom(&COUNTER).unwrap();
+        c8.cpu.timer_sound = 10;
+
+        // The default clock is the tick rate at 60 Hz
+        assert_eq!(
+            c8.advance(Duration::from_millis(100)).unwrap(),
+            (6, StopReason::FrameComplete)
+        );
+        assert_eq!(c8.cpu.timer_sound, 4);
+        assert_eq!(c8.cpu.v[0], 30);
+    }
+
+    #[test]
+    fn breakpoint() {
+        let mut c8 = Chip8::default();
+        c8.load_rom(&COUNTER).unwrap();
+        c8.debugger.add_breakpoint(0x202);
+
+        assert_eq!(
+            c8.advance(Duration::from_secs(1)).unwrap(),
+            (0, StopReason::Breakpoint(0x202))
+        );
+        assert_eq!(c8.cpu.pc, 0x202);
+        assert_eq!(c8.cpu.v[0], 1);
+
+        // 0x200: EXIT
+        c8.load_rom(&[0x00, 0xFD]).unwrap();
+        c8.reset();
+        assert_eq!(
+            c8.advance(Duration::from_millis(50)).unwrap(),
+            (3, StopReason::Halted)
+        );
+    }
+}
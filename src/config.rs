@@ -1,9 +1,66 @@
+use crate::{Quirks, Timing};
+
+const CHIP8_MEMORY_SIZE: usize = 4096;
+const XO_CHIP_MEMORY_SIZE: usize = 65536;
+
+/// The variant of the CHIP-8 language run by the interpreter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// The original CHIP-8 along with the Super-Chip extensions, with 4 KiB of memory.
+    #[default]
+    Chip8,
+    /// The XO-CHIP extensions from Octo: 64 KiB of memory, bitplanes and extended opcodes.
+    XoChip,
+}
+
+impl Platform {
+    /// The amount of bytes of RAM available to the platform.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 => CHIP8_MEMORY_SIZE,
+            Platform::XoChip => XO_CHIP_MEMORY_SIZE,
+        }
+    }
+}
+
+/// What happens when an instruction accesses memory past the end or checks a key
+/// past 0xF.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutOfBounds {
+    /// Stop with a [ChipError](crate::ChipError).
+    #[default]
+    Error,
+    /// Wrap around to the start of the memory and only use the low nibble of keys.
+    /// The index register also wraps around when FX1E goes past 0xFFFF.
+    Wrap,
+}
+
 /// Settings to modify the behaviour of the interpreter.
+#[derive(Clone, Debug)]
 pub struct Config {
     /// The location in memory where the loaded ROM data starts.
     pub rom_base_addr: usize,
     /// How many CPU cycles occur before every frame render cycle.
     pub tick_rate: u32,
+    /// How many instructions run per second with [`Chip8::advance`](crate::Chip8::advance).
+    /// Follows the tick rate at 60 Hz when set to None.
+    pub clock_rate: Option<f64>,
+    /// How long instructions take to run. See [Timing] for the modes.
+    pub timing: Timing,
+    /// The variant of CHIP-8 to run. Determines the size of the memory and which
+    /// opcodes are available. Call [`Chip8::reset`](crate::Chip8::reset) after
+    /// changing it so the memory is resized.
+    pub platform: Platform,
+    /// The behaviour of the ambiguous instructions. See [Quirks] for the presets.
+    pub quirks: Quirks,
+    /// How many frames [`Chip8::rewind`](crate::Chip8::rewind) can go back. Recording
+    /// the history is disabled when set to 0.
+    pub rewind_depth: usize,
+    /// The seed for the random number generator used by CXNN. The same seed always
+    /// produces the same numbers. A random seed is picked when set to None.
+    pub seed: Option<u64>,
+    /// How instructions handle addresses past the end of the memory and invalid keys.
+    pub out_of_bounds: OutOfBounds,
 }
 
 impl Default for Config {
@@ -11,6 +68,13 @@ impl Default for Config {
         Config {
             rom_base_addr: 0x200,
             tick_rate: 10,
+            clock_rate: None,
+            timing: Timing::default(),
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            rewind_depth: 0,
+            seed: None,
+            out_of_bounds: OutOfBounds::default(),
         }
     }
 }
@@ -1,12 +1,41 @@
 mod opcodes;
 
+use std::collections::VecDeque;
+
+use crate::disassembler::disassemble_instruction;
 use crate::errors::ChipError;
-use crate::Screen;
+use crate::{ChipRng, Config, OutOfBounds, Screen, TraceRecord, TraceSink, TraceState};
 use opcodes::execute;
-use opcodes::Opcode;
+pub(crate) use opcodes::Opcode;
 
 const NUM_REGISTERS: usize = 0x10;
 const STACK_SIZE: usize = 16;
+const NUM_RPL_FLAGS: usize = 16;
+pub(crate) const PATTERN_SIZE: usize = 16;
+pub(crate) const DEFAULT_PITCH: u8 = 64;
+
+/// Whether a memory access read or wrote the byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A byte of memory read or written by an instruction. Instruction fetches are
+/// not recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+/// A change of a key of the keypad (0x0 - 0xF).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    Down(u8),
+    Up(u8),
+}
 
 /// The CPU of the machine. In charge of interpreting all the commands from
 /// the loaded ROM.
@@ -20,6 +49,22 @@ pub struct Cpu {
     pub timer_sound: u8,
     pub stack: [u16; STACK_SIZE],
     pub keypad: [bool; 16],
+
+    // Super-Chip and XO-CHIP
+    pub rpl: [u8; NUM_RPL_FLAGS],
+    pub halted: bool,
+    /// The 1-bit audio pattern loaded by F002, played instead of the plain tone once set.
+    pub pattern: Option<[u8; PATTERN_SIZE]>,
+    /// The playback rate of the audio pattern set by FX3A, see [`Audio::pattern_rate`](crate::Audio::pattern_rate).
+    pub pitch: u8,
+
+    // The key an FX0A waiting for a release saw pressed
+    pub(crate) waiting_key: Option<u8>,
+    pub(crate) key_events: VecDeque<KeyEvent>,
+    // Every key event of the frame, which FX0A doesn't consume
+    pub(crate) frame_key_events: Vec<KeyEvent>,
+    accesses: Vec<MemoryAccess>,
+    tracer: Option<Box<dyn TraceSink>>,
 }
 
 impl Cpu {
@@ -49,39 +94,249 @@ impl Cpu {
         Ok(value)
     }
 
-    /// Performs a Fetch-Decode-Execute cycle.
-    pub fn step(&mut self, memory: &mut [u8], screen: &mut Screen) -> Result<(), ChipError> {
+    /// Performs a Fetch-Decode-Execute cycle. Nothing happens once the CPU has been
+    /// halted by the Super-Chip exit instruction (00FD).
+    pub fn step(
+        &mut self,
+        memory: &mut [u8],
+        screen: &mut Screen,
+        config: &Config,
+        rng: &mut dyn ChipRng,
+    ) -> Result<(), ChipError> {
+        self.accesses.clear();
+        if self.halted {
+            return Ok(());
+        }
+
+        if self.tracer.is_some() {
+            return self.traced_step(memory, screen, config, rng);
+        }
+
         // Fetch
-        let opcode_hex = self.fetch(memory)?;
+        let opcode_hex = self.fetch(memory, config)?;
 
         // Decode
         let opcode = Opcode::from(opcode_hex);
 
         // Execute
-        execute(opcode, self, memory, screen)?;
+        execute(opcode, self, memory, screen, config, rng)?;
 
         Ok(())
     }
 
-    fn fetch(&mut self, memory: &mut [u8]) -> Result<u16, ChipError> {
-        if (self.pc + 1) >= memory.len() {
-            return Err(ChipError::AddressOutOfBounds {
-                address: self.pc + 1,
-                limit: memory.len(),
-            });
+    // Runs the instruction and reports what changed to the tracer
+    fn traced_step(
+        &mut self,
+        memory: &mut [u8],
+        screen: &mut Screen,
+        config: &Config,
+        rng: &mut dyn ChipRng,
+    ) -> Result<(), ChipError> {
+        let pc = self.pc;
+        let v = self.v;
+        let state = TraceState {
+            v,
+            i: self.i,
+            sp: self.sp as u8,
+            timer_delay: self.timer_delay,
+            timer_sound: self.timer_sound,
+        };
+        let end = memory.len().min(pc.saturating_add(4));
+        let mnemonic = match pc < end {
+            true => disassemble_instruction(&memory[pc..end], pc, config).mnemonic,
+            false => String::new(),
+        };
+
+        let opcode_hex = self.fetch(memory, config)?;
+        execute(Opcode::from(opcode_hex), self, memory, screen, config, rng)?;
+
+        let record = TraceRecord {
+            state,
+            pc,
+            opcode: opcode_hex,
+            mnemonic,
+            registers: (0..NUM_REGISTERS)
+                .filter(|&r| self.v[r] != v[r])
+                .map(|r| (r as u8, self.v[r]))
+                .collect(),
+            i: self.i,
+            writes: self
+                .accesses
+                .iter()
+                .filter(|a| a.kind == AccessKind::Write)
+                .map(|a| (a.address, a.value))
+                .collect(),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&record);
         }
 
-        let hi = memory[self.pc] as u16;
-        let lo = memory[self.pc + 1] as u16;
+        Ok(())
+    }
+
+    /// Report every instruction executed from now on to the sink, or stop tracing
+    /// with None.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn TraceSink>>) {
+        self.tracer = tracer;
+    }
+
+    /// Stop tracing and return the sink that was receiving the records.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn TraceSink>> {
+        self.tracer.take()
+    }
+
+    /// The memory read and written by the last executed instruction.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    // Memory accesses made by instructions go through these two so they get recorded
+    // and follow the out of bounds policy
+    fn read(&mut self, memory: &[u8], address: usize, config: &Config) -> Result<u8, ChipError> {
+        let address = bounded_address(address, memory.len(), config)?;
+        let value = memory[address];
+        self.accesses.push(MemoryAccess {
+            address,
+            value,
+            kind: AccessKind::Read,
+        });
+
+        Ok(value)
+    }
+
+    fn write(
+        &mut self,
+        memory: &mut [u8],
+        address: usize,
+        value: u8,
+        config: &Config,
+    ) -> Result<(), ChipError> {
+        let address = bounded_address(address, memory.len(), config)?;
+        memory[address] = value;
+        self.accesses.push(MemoryAccess {
+            address,
+            value,
+            kind: AccessKind::Write,
+        });
+
+        Ok(())
+    }
+
+    // Whether the key in the register is pressed, following the out of bounds policy
+    fn key_pressed(&self, register: u8, config: &Config) -> Result<bool, ChipError> {
+        let key = self.v[register as usize];
+        match (key, config.out_of_bounds) {
+            (0x0..=0xF, _) | (_, OutOfBounds::Wrap) => Ok(self.keypad[(key & 0xF) as usize]),
+            (_, OutOfBounds::Error) => Err(ChipError::InvalidKey(key)),
+        }
+    }
+
+    /// Press a key of the keypad. Pressing a key already held does nothing.
+    pub fn key_down(&mut self, key: u8) -> Result<(), ChipError> {
+        let pressed = self
+            .keypad
+            .get_mut(key as usize)
+            .ok_or(ChipError::InvalidKey(key))?;
+        if !*pressed {
+            *pressed = true;
+            self.key_events.push_back(KeyEvent::Down(key));
+            self.frame_key_events.push(KeyEvent::Down(key));
+        }
+
+        Ok(())
+    }
+
+    /// Release a key of the keypad. Releasing a key that isn't held does nothing.
+    pub fn key_up(&mut self, key: u8) -> Result<(), ChipError> {
+        let pressed = self
+            .keypad
+            .get_mut(key as usize)
+            .ok_or(ChipError::InvalidKey(key))?;
+        if *pressed {
+            *pressed = false;
+            self.key_events.push_back(KeyEvent::Up(key));
+            self.frame_key_events.push(KeyEvent::Up(key));
+        }
+
+        Ok(())
+    }
+
+    // Key events only count during the frame they happened in
+    pub(crate) fn clear_key_events(&mut self) {
+        self.key_events.clear();
+        self.frame_key_events.clear();
+    }
+
+    // The key events since the end of the last frame, in the order they happened
+    pub(crate) fn frame_key_events(&self) -> &[KeyEvent] {
+        &self.frame_key_events
+    }
+
+    // The key pressed since the last call, if any
+    fn next_key_press(&mut self) -> Option<u8> {
+        while let Some(event) = self.key_events.pop_front() {
+            if let KeyEvent::Down(key) = event {
+                return Some(key);
+            }
+        }
+
+        None
+    }
+
+    // The key released since the last call after being pressed. A key held since
+    // before the wait started counts as pressed.
+    fn next_key_release(&mut self) -> Option<u8> {
+        while let Some(event) = self.key_events.pop_front() {
+            match (event, self.waiting_key) {
+                (KeyEvent::Down(key), None) => self.waiting_key = Some(key),
+                (KeyEvent::Up(key), Some(waiting)) if key == waiting => {
+                    self.waiting_key = None;
+                    return Some(key);
+                }
+                _ => {}
+            }
+        }
+
+        match self.waiting_key {
+            None => {
+                self.waiting_key = self.keypad.iter().position(|&p| p).map(|key| key as u8);
+                None
+            }
+            // Released by changing the keypad directly
+            Some(key) if !self.keypad[key as usize] => self.waiting_key.take(),
+            Some(_) => None,
+        }
+    }
+
+    // Adds to the index register, following the out of bounds policy when it goes past 0xFFFF
+    fn add_to_index(&mut self, value: u16, config: &Config) -> Result<(), ChipError> {
+        self.i = match (self.i.checked_add(value), config.out_of_bounds) {
+            (Some(i), _) => i,
+            (None, OutOfBounds::Wrap) => self.i.wrapping_add(value),
+            (None, OutOfBounds::Error) => {
+                return Err(ChipError::AddressOutOfBounds {
+                    address: self.i as usize + value as usize,
+                    limit: u16::MAX as usize + 1,
+                })
+            }
+        };
+
+        Ok(())
+    }
+
+    fn fetch(&mut self, memory: &mut [u8], config: &Config) -> Result<u16, ChipError> {
+        let lo_address = bounded_address(self.pc.saturating_add(1), memory.len(), config)?;
+        let hi_address = bounded_address(self.pc, memory.len(), config)?;
 
         // The CHIP-8 is big endian
-        let opcode: u16 = (hi << 8) | lo;
-        self.pc += 2;
+        let opcode = (memory[hi_address] as u16) << 8 | memory[lo_address] as u16;
+        self.pc = hi_address + 2;
 
         Ok(opcode)
     }
 
-    /// Set all registers, stack and timers to zero.
+    /// Set all registers, stack and timers to zero. The RPL user flags are persistent
+    /// storage and keep their values.
     pub fn reset(&mut self) {
         self.v = [0; NUM_REGISTERS];
         self.i = 0;
@@ -91,6 +346,21 @@ impl Cpu {
         self.timer_sound = 0;
         self.stack = [0; STACK_SIZE];
         self.keypad = [false; 16];
+        self.halted = false;
+        self.pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        self.waiting_key = None;
+        self.key_events.clear();
+        self.frame_key_events.clear();
+        self.accesses.clear();
+    }
+}
+
+fn bounded_address(address: usize, limit: usize, config: &Config) -> Result<usize, ChipError> {
+    match (address < limit, config.out_of_bounds) {
+        (true, _) => Ok(address),
+        (false, OutOfBounds::Wrap) => Ok(address % limit),
+        (false, OutOfBounds::Error) => Err(ChipError::AddressOutOfBounds { address, limit }),
     }
 }
 
@@ -105,6 +375,15 @@ impl Default for Cpu {
             timer_sound: 0,
             stack: [0; STACK_SIZE],
             keypad: [false; 16],
+            rpl: [0; NUM_RPL_FLAGS],
+            halted: false,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            accesses: Vec::new(),
+            waiting_key: None,
+            key_events: VecDeque::new(),
+            frame_key_events: Vec::new(),
+            tracer: None,
         }
     }
 }
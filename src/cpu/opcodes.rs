@@ -1,15 +1,14 @@
-use rand::Rng;
-
-use super::Cpu;
+use super::{bounded_address, Cpu, PATTERN_SIZE};
 use crate::errors::ChipError;
-use crate::memory::FONT_BASE_ADDRESS;
-use crate::Screen;
+use crate::memory::{BIG_FONT_BASE_ADDRESS, FONT_BASE_ADDRESS};
+use crate::screen::NUM_PLANES;
+use crate::{ChipRng, Config, IndexIncrement, KeyWait, Platform, Screen};
 
 pub struct Opcode {
-    hex: u16,
+    pub hex: u16,
 
     // First nibble
-    prefix: u8,
+    pub prefix: u8,
     // Second nibble
     pub x: u8,
     // Third nibble
@@ -19,7 +18,7 @@ pub struct Opcode {
     // Second + Third nibble
     pub nn: u8,
     // Second + Third + Fourth nibble
-    nnn: u16,
+    pub nnn: u16,
 }
 
 impl From<u16> for Opcode {
@@ -41,43 +40,93 @@ pub fn execute(
     cpu: &mut Cpu,
     memory: &mut [u8],
     screen: &mut Screen,
+    config: &Config,
+    rng: &mut dyn ChipRng,
 ) -> Result<(), ChipError> {
-    let mut rng = rand::thread_rng();
-
     match opcode.prefix {
-        0x0 => execute_prefix_0(opcode, cpu, screen)?,
+        0x0 => execute_prefix_0(opcode, cpu, screen, config)?,
         0x1 => cpu.pc = opcode.nnn as usize,
         0x2 => call_subroutine(opcode, cpu)?,
-        0x3 => skip_if(cpu.v[opcode.x as usize] == opcode.nn, cpu),
-        0x4 => skip_if(cpu.v[opcode.x as usize] != opcode.nn, cpu),
-        0x5 => skip_if(cpu.v[opcode.x as usize] == cpu.v[opcode.y as usize], cpu),
+        0x3 => skip_if(cpu.v[opcode.x as usize] == opcode.nn, cpu, memory, config),
+        0x4 => skip_if(cpu.v[opcode.x as usize] != opcode.nn, cpu, memory, config),
+        0x5 => execute_prefix_5(opcode, cpu, memory, config)?,
         0x6 => cpu.v[opcode.x as usize] = opcode.nn,
         0x7 => cpu.v[opcode.x as usize] = cpu.v[opcode.x as usize].wrapping_add(opcode.nn),
-        0x8 => execute_prefix_8(opcode, cpu)?,
-        0x9 => skip_if(cpu.v[opcode.x as usize] != cpu.v[opcode.y as usize], cpu),
+        0x8 => execute_prefix_8(opcode, cpu, config)?,
+        0x9 => skip_if(
+            cpu.v[opcode.x as usize] != cpu.v[opcode.y as usize],
+            cpu,
+            memory,
+            config,
+        ),
         0xA => cpu.i = opcode.nnn,
-        0xB => cpu.pc = opcode.nnn as usize + cpu.v[0] as usize,
-        0xC => cpu.v[opcode.x as usize] = rng.gen_range(0x00..0xFF) & opcode.nn,
-        0xD => draw_sprite(opcode, cpu, memory, screen)?,
-        0xE => execute_prefix_e(opcode, cpu)?,
-        0xF => execute_prefix_f(opcode, cpu, memory)?,
+        0xB => jump_with_offset(opcode, cpu, memory, config)?,
+        0xC => cpu.v[opcode.x as usize] = rng.next_u8() & opcode.nn,
+        0xD => draw_sprite(opcode, cpu, memory, screen, config)?,
+        0xE => execute_prefix_e(opcode, cpu, memory, config)?,
+        0xF => execute_prefix_f(opcode, cpu, memory, screen, config)?,
         _ => return Err(ChipError::OpcodeNotImplemented { opcode: opcode.hex }),
     }
 
     Ok(())
 }
 
-fn execute_prefix_0(opcode: Opcode, cpu: &mut Cpu, screen: &mut Screen) -> Result<(), ChipError> {
+fn execute_prefix_0(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    screen: &mut Screen,
+    config: &Config,
+) -> Result<(), ChipError> {
+    let xo_chip = config.platform == Platform::XoChip;
+
     match opcode.hex {
-        0x00E0 => screen.clear_screen(),
+        0x00E0 => screen.clear_planes(screen.selected_planes()),
         0x00EE => cpu.pc = cpu.pop()? as usize,
-        _ => (),
+        0x00FB => screen.scroll_right(4),
+        0x00FC => screen.scroll_left(4),
+        0x00FD => cpu.halted = true,
+        0x00FE => screen.set_hires(false),
+        0x00FF => screen.set_hires(true),
+        _ if opcode.hex & 0xFFF0 == 0x00C0 => screen.scroll_down(opcode.n as usize),
+        _ if opcode.hex & 0xFFF0 == 0x00D0 && xo_chip => screen.scroll_up(opcode.n as usize),
+        _ => return Err(ChipError::OpcodeNotImplemented { opcode: opcode.hex }),
+    }
+
+    Ok(())
+}
+
+fn execute_prefix_5(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
+    let xo_chip = config.platform == Platform::XoChip;
+
+    match opcode.n {
+        0x0 => skip_if(
+            cpu.v[opcode.x as usize] == cpu.v[opcode.y as usize],
+            cpu,
+            memory,
+            config,
+        ),
+        0x2 if xo_chip => save_register_range(opcode, cpu, memory, config)?,
+        0x3 if xo_chip => load_register_range(opcode, cpu, memory, config)?,
+        _ => {
+            return Err(ChipError::OpcodeNotImplemented { opcode: opcode.hex });
+        }
     }
 
     Ok(())
 }
 
-fn execute_prefix_8(opcode: Opcode, cpu: &mut Cpu) -> Result<(), ChipError> {
+fn execute_prefix_8(opcode: Opcode, cpu: &mut Cpu, config: &Config) -> Result<(), ChipError> {
+    let quirks = &config.quirks;
+    let shift_src = match quirks.shift_uses_vy {
+        true => opcode.y,
+        false => opcode.x,
+    };
+
     match opcode.n {
         0x0 => cpu.v[opcode.x as usize] = cpu.v[opcode.y as usize],
         0x1 => cpu.v[opcode.x as usize] |= cpu.v[opcode.y as usize],
@@ -85,21 +134,30 @@ fn execute_prefix_8(opcode: Opcode, cpu: &mut Cpu) -> Result<(), ChipError> {
         0x3 => cpu.v[opcode.x as usize] ^= cpu.v[opcode.y as usize],
         0x4 => add_registers(opcode.x, opcode.y, cpu),
         0x5 => sub_registers(opcode.x, opcode.x, opcode.y, cpu),
-        0x6 => shift_right(opcode.x, cpu),
+        0x6 => shift_right(opcode.x, shift_src, cpu),
         0x7 => sub_registers(opcode.x, opcode.y, opcode.x, cpu),
-        0xE => shift_left(opcode.x, cpu),
+        0xE => shift_left(opcode.x, shift_src, cpu),
         _ => {
             return Err(ChipError::OpcodeNotImplemented { opcode: opcode.hex });
         }
     }
 
+    if quirks.logic_resets_vf && (0x1..=0x3).contains(&opcode.n) {
+        cpu.v[0xF] = 0;
+    }
+
     Ok(())
 }
 
-fn execute_prefix_e(opcode: Opcode, cpu: &mut Cpu) -> Result<(), ChipError> {
+fn execute_prefix_e(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
     match opcode.hex & 0x00FF {
-        0x9E => skip_if(cpu.keypad[cpu.v[opcode.x as usize] as usize], cpu),
-        0xA1 => skip_if(!cpu.keypad[cpu.v[opcode.x as usize] as usize], cpu),
+        0x9E => skip_if(cpu.key_pressed(opcode.x, config)?, cpu, memory, config),
+        0xA1 => skip_if(!cpu.key_pressed(opcode.x, config)?, cpu, memory, config),
         _ => {
             return Err(ChipError::OpcodeNotImplemented { opcode: opcode.hex });
         }
@@ -107,17 +165,32 @@ fn execute_prefix_e(opcode: Opcode, cpu: &mut Cpu) -> Result<(), ChipError> {
     Ok(())
 }
 
-fn execute_prefix_f(opcode: Opcode, cpu: &mut Cpu, memory: &mut [u8]) -> Result<(), ChipError> {
+fn execute_prefix_f(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    screen: &mut Screen,
+    config: &Config,
+) -> Result<(), ChipError> {
+    let xo_chip = config.platform == Platform::XoChip;
+
     match opcode.hex & 0x00FF {
+        0x00 if opcode.x == 0 && xo_chip => load_long_index(cpu, memory, config)?,
+        0x01 if xo_chip => screen.select_planes(opcode.x),
+        0x02 if opcode.x == 0 && xo_chip => load_audio_pattern(cpu, memory, config)?,
         0x07 => cpu.v[opcode.x as usize] = cpu.timer_delay,
-        0x0A => get_input(opcode, cpu),
+        0x0A => get_input(opcode, cpu, config),
         0x15 => cpu.timer_delay = cpu.v[opcode.x as usize],
         0x18 => cpu.timer_sound = cpu.v[opcode.x as usize],
-        0x1E => cpu.i += cpu.v[opcode.x as usize] as u16,
+        0x1E => cpu.add_to_index(cpu.v[opcode.x as usize] as u16, config)?,
         0x29 => cpu.i = (FONT_BASE_ADDRESS + (cpu.v[opcode.x as usize] as usize * 5)) as u16,
-        0x33 => store_bcd(opcode, cpu, memory),
-        0x55 => store_registers(opcode, cpu, memory),
-        0x65 => retrieve_registers(opcode, cpu, memory),
+        0x30 => cpu.i = (BIG_FONT_BASE_ADDRESS + (cpu.v[opcode.x as usize] as usize * 10)) as u16,
+        0x3A if xo_chip => cpu.pitch = cpu.v[opcode.x as usize],
+        0x33 => store_bcd(opcode, cpu, memory, config)?,
+        0x55 => store_registers(opcode, cpu, memory, config)?,
+        0x65 => retrieve_registers(opcode, cpu, memory, config)?,
+        0x75 => store_rpl_flags(opcode, cpu),
+        0x85 => retrieve_rpl_flags(opcode, cpu),
         _ => {
             return Err(ChipError::OpcodeNotImplemented { opcode: opcode.hex });
         }
@@ -126,6 +199,16 @@ fn execute_prefix_f(opcode: Opcode, cpu: &mut Cpu, memory: &mut [u8]) -> Result<
     Ok(())
 }
 
+fn load_audio_pattern(cpu: &mut Cpu, memory: &[u8], config: &Config) -> Result<(), ChipError> {
+    let mut pattern = [0; PATTERN_SIZE];
+    for (offset, byte) in pattern.iter_mut().enumerate() {
+        *byte = cpu.read(memory, cpu.i as usize + offset, config)?;
+    }
+    cpu.pattern = Some(pattern);
+
+    Ok(())
+}
+
 fn call_subroutine(opcode: Opcode, cpu: &mut Cpu) -> Result<(), ChipError> {
     cpu.push(cpu.pc as u16)?;
     cpu.pc = opcode.nnn as usize;
@@ -133,35 +216,103 @@ fn call_subroutine(opcode: Opcode, cpu: &mut Cpu) -> Result<(), ChipError> {
     Ok(())
 }
 
-fn skip_if(skip: bool, cpu: &mut Cpu) {
+fn jump_with_offset(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &[u8],
+    config: &Config,
+) -> Result<(), ChipError> {
+    let offset_reg = match config.quirks.jump_uses_vx {
+        true => opcode.x as usize,
+        false => 0,
+    };
+
+    let address = opcode.nnn as usize + cpu.v[offset_reg] as usize;
+    cpu.pc = bounded_address(address, memory.len(), config)?;
+
+    Ok(())
+}
+
+fn skip_if(skip: bool, cpu: &mut Cpu, memory: &[u8], config: &Config) {
     if skip {
-        cpu.pc += 2;
+        cpu.pc += instruction_size(cpu.pc, memory, config);
     }
 }
 
+// The XO-CHIP F000 NNNN instruction is 4 bytes long and has to be skipped as a whole
+fn instruction_size(address: usize, memory: &[u8], config: &Config) -> usize {
+    let long_load = config.platform == Platform::XoChip
+        && memory.get(address) == Some(&0xF0)
+        && memory.get(address + 1) == Some(&0x00);
+
+    match long_load {
+        true => 4,
+        false => 2,
+    }
+}
+
+fn load_long_index(cpu: &mut Cpu, memory: &[u8], config: &Config) -> Result<(), ChipError> {
+    let lo_address = bounded_address(cpu.pc.saturating_add(1), memory.len(), config)?;
+    let hi_address = bounded_address(cpu.pc, memory.len(), config)?;
+
+    cpu.i = (memory[hi_address] as u16) << 8 | memory[lo_address] as u16;
+    cpu.pc = hi_address + 2;
+
+    Ok(())
+}
+
 fn draw_sprite(
     opcode: Opcode,
     cpu: &mut Cpu,
     memory: &mut [u8],
     screen: &mut Screen,
+    config: &Config,
 ) -> Result<(), ChipError> {
-    let sprite_height = opcode.n as usize;
-    let sprite_x = cpu.v[opcode.x as usize] as usize;
-    let sprite_y = cpu.v[opcode.y as usize] as usize;
-    let sprite_base_addr = cpu.i as usize;
+    // A height of 0 draws a 16x16 Super-Chip sprite, stored as two bytes per row
+    let (sprite_width, sprite_height) = match opcode.n {
+        0 => (16, 16),
+        n => (8, n as usize),
+    };
+    // The starting position always wraps, only the pixels past the edges can be clipped
+    let sprite_x = cpu.v[opcode.x as usize] as usize % screen.width;
+    let sprite_y = cpu.v[opcode.y as usize] as usize % screen.height;
+    let clip = config.quirks.clip_sprites;
+    let bytes_per_row = sprite_width / 8;
+    let selected_planes = screen.selected_planes();
+    let mut sprite_base_addr = cpu.i as usize;
     let mut collided = false;
 
-    for y in 0..sprite_height {
-        let sprite_hslice: u8 = memory[sprite_base_addr + y];
+    // XO-CHIP draws the sprite once for each selected plane, with the data for
+    // each plane following the previous one in memory
+    for plane in (0..NUM_PLANES).map(|p| 1 << p) {
+        if selected_planes & plane == 0 {
+            continue;
+        }
 
-        for x in 0..8 {
-            if (sprite_hslice & (0x80 >> x)) != 0 {
-                let pos_x = (sprite_x + x) % screen.width;
-                let pos_y = (sprite_y + y) % screen.height;
-                collided |= screen.get_pixel(pos_x, pos_y);
-                screen.toggle_pixel(pos_x, pos_y);
+        for y in 0..sprite_height {
+            let row_addr = sprite_base_addr + y * bytes_per_row;
+            let sprite_hslice: u16 = match bytes_per_row {
+                2 => {
+                    (cpu.read(memory, row_addr, config)? as u16) << 8
+                        | cpu.read(memory, row_addr + 1, config)? as u16
+                }
+                _ => (cpu.read(memory, row_addr, config)? as u16) << 8,
+            };
+
+            for x in 0..sprite_width {
+                let clipped =
+                    clip && (sprite_x + x >= screen.width || sprite_y + y >= screen.height);
+
+                if (sprite_hslice & (0x8000 >> x)) != 0 && !clipped {
+                    let pos_x = (sprite_x + x) % screen.width;
+                    let pos_y = (sprite_y + y) % screen.height;
+                    collided |= screen.get_pixel(pos_x, pos_y) & plane != 0;
+                    screen.toggle_pixel(pos_x, pos_y, plane);
+                }
             }
         }
+
+        sprite_base_addr += sprite_height * bytes_per_row;
     }
 
     if collided {
@@ -191,30 +342,44 @@ fn sub_registers(reg_store: u8, reg_lhs: u8, reg_rhs: u8, cpu: &mut Cpu) {
     };
 }
 
-fn shift_right(reg: u8, cpu: &mut Cpu) {
-    cpu.v[0xF] = cpu.v[reg as usize] & 0x01;
-    cpu.v[reg as usize] >>= 1;
+fn shift_right(reg_store: u8, reg_src: u8, cpu: &mut Cpu) {
+    let value = cpu.v[reg_src as usize];
+    cpu.v[reg_store as usize] = value >> 1;
+    cpu.v[0xF] = value & 0x01;
 }
 
-fn shift_left(reg: u8, cpu: &mut Cpu) {
-    cpu.v[0xF] = (cpu.v[reg as usize] >> 7) & 0x01;
-    cpu.v[reg as usize] <<= 1;
+fn shift_left(reg_store: u8, reg_src: u8, cpu: &mut Cpu) {
+    let value = cpu.v[reg_src as usize];
+    cpu.v[reg_store as usize] = value << 1;
+    cpu.v[0xF] = (value >> 7) & 0x01;
 }
 
-fn get_input(opcode: Opcode, cpu: &mut Cpu) {
-    let input = cpu.keypad.iter().position(|&x| x);
+fn get_input(opcode: Opcode, cpu: &mut Cpu, config: &Config) {
+    let input = match config.quirks.key_wait {
+        KeyWait::Held => cpu.keypad.iter().position(|&x| x).map(|key| key as u8),
+        KeyWait::Press => cpu.next_key_press(),
+        KeyWait::Release => cpu.next_key_release(),
+    };
 
     match input {
-        Some(key) => cpu.v[opcode.x as usize] = key as u8,
+        Some(key) => cpu.v[opcode.x as usize] = key,
         None => cpu.pc -= 2,
     };
 }
 
-fn store_bcd(opcode: Opcode, cpu: &mut Cpu, memory: &mut [u8]) {
+fn store_bcd(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
     let (bcd2, bcd1, bcd0) = bcd(cpu.v[opcode.x as usize]);
-    memory[cpu.i as usize] = bcd2;
-    memory[cpu.i as usize + 1] = bcd1;
-    memory[cpu.i as usize + 2] = bcd0;
+    let addr = cpu.i as usize;
+    cpu.write(memory, addr, bcd2, config)?;
+    cpu.write(memory, addr + 1, bcd1, config)?;
+    cpu.write(memory, addr + 2, bcd0, config)?;
+
+    Ok(())
 }
 
 fn bcd(input: u8) -> (u8, u8, u8) {
@@ -252,414 +417,1078 @@ fn bcd(input: u8) -> (u8, u8, u8) {
     (bcd2, bcd1, bcd0)
 }
 
-fn store_registers(opcode: Opcode, cpu: &mut Cpu, memory: &mut [u8]) {
+fn store_registers(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
     for i in 0..(opcode.x + 1) as usize {
-        memory[cpu.i as usize + i] = cpu.v[i];
+        cpu.write(memory, cpu.i as usize + i, cpu.v[i], config)?;
     }
+    increment_index(opcode, cpu, config)
 }
 
-fn retrieve_registers(opcode: Opcode, cpu: &mut Cpu, memory: &mut [u8]) {
+fn retrieve_registers(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
     for i in 0..(opcode.x + 1) as usize {
-        cpu.v[i] = memory[cpu.i as usize + i];
+        cpu.v[i] = cpu.read(memory, cpu.i as usize + i, config)?;
+    }
+    increment_index(opcode, cpu, config)
+}
+
+fn increment_index(opcode: Opcode, cpu: &mut Cpu, config: &Config) -> Result<(), ChipError> {
+    match config.quirks.load_store_index {
+        IndexIncrement::Unchanged => Ok(()),
+        IndexIncrement::ByX => cpu.add_to_index(opcode.x as u16, config),
+        IndexIncrement::ByXPlusOne => cpu.add_to_index(opcode.x as u16 + 1, config),
     }
 }
 
+fn register_range(opcode: &Opcode) -> Vec<usize> {
+    let (x, y) = (opcode.x as usize, opcode.y as usize);
+
+    match x <= y {
+        true => (x..=y).collect(),
+        false => (y..=x).rev().collect(),
+    }
+}
+
+fn save_register_range(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
+    for (offset, reg) in register_range(&opcode).into_iter().enumerate() {
+        cpu.write(memory, cpu.i as usize + offset, cpu.v[reg], config)?;
+    }
+
+    Ok(())
+}
+
+fn load_register_range(
+    opcode: Opcode,
+    cpu: &mut Cpu,
+    memory: &mut [u8],
+    config: &Config,
+) -> Result<(), ChipError> {
+    for (offset, reg) in register_range(&opcode).into_iter().enumerate() {
+        cpu.v[reg] = cpu.read(memory, cpu.i as usize + offset, config)?;
+    }
+
+    Ok(())
+}
+
+fn store_rpl_flags(opcode: Opcode, cpu: &mut Cpu) {
+    let last = opcode.x as usize;
+    cpu.rpl[..=last].copy_from_slice(&cpu.v[..=last]);
+}
+
+fn retrieve_rpl_flags(opcode: Opcode, cpu: &mut Cpu) {
+    let last = opcode.x as usize;
+    cpu.v[..=last].copy_from_slice(&cpu.rpl[..=last]);
+}
+
 #[cfg(test)]
 mod tests {
     use super::bcd;
     use super::Cpu;
     use super::Screen;
+    use super::BIG_FONT_BASE_ADDRESS;
+    use super::{Config, KeyWait, Platform};
     use crate::errors::ChipError;
+    use crate::{ChipRng, OutOfBounds, Quirks, XorShiftRng};
 
-    fn test_setup() -> (Cpu, Screen) {
-        (Cpu::default(), Screen::default())
+    fn test_setup() -> (Cpu, Screen, Config, XorShiftRng) {
+        let rng = XorShiftRng::new(0);
+        (Cpu::default(), Screen::default(), Config::default(), rng)
+    }
+
+    fn xo_chip_setup() -> (Cpu, Screen, Config, XorShiftRng) {
+        let config = Config {
+            platform: Platform::XoChip,
+            ..Default::default()
+        };
+        let rng = XorShiftRng::new(0);
+
+        (Cpu::default(), Screen::default(), config, rng)
     }
 
     #[test]
     fn opcode_00e0() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x00, 0xe0, 0x00, 0x00];
+        screen.set_pixel(5, 10, 1);
+        screen.set_pixel(50, 30, 1);
+
+        assert_eq!(screen.get_pixel(5, 10), 1);
+        assert_eq!(screen.get_pixel(50, 30), 1);
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(5, 10), 0);
+        assert_eq!(screen.get_pixel(50, 30), 0);
+    }
+
+    #[test]
+    fn opcode_00e0_planes() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
         let mut memory: [u8; 4] = [0x00, 0xe0, 0x00, 0x00];
-        screen.set_pixel(5, 10);
-        screen.set_pixel(50, 30);
+        screen.set_pixel(5, 10, 0b11);
+        screen.select_planes(0b10);
 
-        assert!(screen.get_pixel(5, 10));
-        assert!(screen.get_pixel(50, 30));
-        cpu.step(&mut memory, &mut screen).unwrap();
-        assert!(!screen.get_pixel(5, 10));
-        assert!(!screen.get_pixel(50, 30));
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(5, 10), 0b01);
     }
 
     #[test]
     fn opcode_00ee() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x00, 0xee, 0x00, 0x00];
 
         assert_eq!(cpu.pc, 0);
         cpu.push(0x01).unwrap();
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 1);
 
         cpu.pc = 0;
-        let e = cpu.step(&mut memory, &mut screen);
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
         assert!(matches!(e, Err(ChipError::StackUnderflow())));
     }
 
+    #[test]
+    fn opcode_00cn() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x00, 0xC3, 0x00, 0x00];
+        screen.set_pixel(5, 10, 1);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(5, 10), 0);
+        assert_eq!(screen.get_pixel(5, 13), 1);
+    }
+
+    #[test]
+    fn opcode_00dn() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 4] = [0x00, 0xD3, 0x00, 0x00];
+        screen.set_pixel(5, 10, 0b11);
+        screen.select_planes(0b01);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(5, 10), 0b10);
+        assert_eq!(screen.get_pixel(5, 7), 0b01);
+
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        screen.set_pixel(5, 10, 1);
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(
+            e,
+            Err(ChipError::OpcodeNotImplemented { opcode: 0x00D3 })
+        ));
+        assert_eq!(screen.get_pixel(5, 10), 1);
+    }
+
+    #[test]
+    fn opcode_0nnn() {
+        // Calls to machine code routines of the original interpreters
+        for opcode in [0x0000u16, 0x0123, 0x00E1, 0x00FA] {
+            let (mut cpu, mut screen, config, mut rng) = test_setup();
+            let mut memory = opcode.to_be_bytes();
+            let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+            assert!(
+                matches!(e, Err(ChipError::OpcodeNotImplemented { opcode: o }) if o == opcode),
+                "{:#06x}",
+                opcode
+            );
+        }
+    }
+
+    #[test]
+    fn opcode_00fb() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x00, 0xFB, 0x00, 0x00];
+        screen.set_pixel(5, 10, 1);
+        screen.set_pixel(62, 10, 1);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(5, 10), 0);
+        assert_eq!(screen.get_pixel(9, 10), 1);
+        assert_eq!(screen.get_pixel(2, 10), 0);
+    }
+
+    #[test]
+    fn opcode_00fc() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x00, 0xFC, 0x00, 0x00];
+        screen.set_pixel(5, 10, 1);
+        screen.set_pixel(2, 10, 1);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(5, 10), 0);
+        assert_eq!(screen.get_pixel(1, 10), 1);
+        assert_eq!(screen.get_pixel(62, 10), 0);
+    }
+
+    #[test]
+    fn opcode_00fd() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x00, 0xFD, 0x12, 0x34];
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 2);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn opcode_00fe_00ff() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x00, 0xFF, 0x00, 0xFE];
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert!(screen.is_hires());
+        assert_eq!(screen.width, 128);
+        assert_eq!(screen.height, 64);
+        screen.set_pixel(127, 63, 1);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert!(!screen.is_hires());
+        assert_eq!(screen.width, 64);
+        assert_eq!(screen.height, 32);
+    }
+
     #[test]
     fn opcode_1nnn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x12, 0x34, 0x00, 0x00];
 
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 0x234);
     }
 
     #[test]
     fn opcode_2nnn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x00, 0x21, 0x23, 0x00];
         cpu.pc = 1;
 
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 0x123);
         assert_eq!(cpu.stack[cpu.sp], 0x03);
     }
 
     #[test]
     fn opcode_3xnn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x31, 0x23, 0x00, 0x00];
 
         cpu.v[1] = 0x23;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 4);
 
         cpu.pc = 0;
         cpu.v[1] = 0x22;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 2);
     }
 
     #[test]
     fn opcode_4xnn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x41, 0x23, 0x00, 0x00];
 
         cpu.v[1] = 0x32;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 4);
 
         cpu.pc = 0;
         cpu.v[1] = 0x23;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 2);
     }
 
     #[test]
     fn opcode_5xy0() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x51, 0x20, 0x00, 0x00];
 
         cpu.v[1] = 0x23;
         cpu.v[2] = 0x23;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 4);
 
         cpu.pc = 0;
         cpu.v[1] = 0x32;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 2);
     }
 
+    #[test]
+    fn opcode_5xy2() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 8] = [0x51, 0x32, 0x53, 0x12, 0x00, 0x00, 0x00, 0x00];
+
+        cpu.i = 4;
+        cpu.v[1] = 0x11;
+        cpu.v[2] = 0x22;
+        cpu.v[3] = 0x33;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(memory[4..], [0x11, 0x22, 0x33, 0x00]);
+        assert_eq!(cpu.i, 4);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(memory[4..], [0x33, 0x22, 0x11, 0x00]);
+
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(e, Err(ChipError::OpcodeNotImplemented { .. })));
+    }
+
+    #[test]
+    fn opcode_5xy3() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 8] = [0x51, 0x33, 0x53, 0x13, 0x11, 0x22, 0x33, 0x00];
+
+        cpu.i = 4;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[1..4], [0x11, 0x22, 0x33]);
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[1..4], [0x33, 0x22, 0x11]);
+    }
+
     #[test]
     fn opcode_6xnn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x62, 0xF1, 0x00, 0x00];
 
         assert_eq!(cpu.v[0x2], 0);
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x2], 0xF1);
     }
 
     #[test]
     fn opcode_7xnn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x75, 0xA1, 0x00, 0x00];
 
         cpu.v[0x5] = 0x32;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x5], 0xD3);
     }
 
     #[test]
     fn opcode_8xy0() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x20, 0x00, 0x00];
 
         cpu.v[0x2] = 0x02;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0x02);
     }
 
     #[test]
     fn opcode_8xy1() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x21, 0x00, 0x00];
 
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0x00);
 
         cpu.pc = 0;
         cpu.v[0x1] = 0x40;
         cpu.v[0x2] = 0x12;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0x52);
     }
 
+    #[test]
+    fn opcode_8xy1_logic_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x81, 0x21, 0x00, 0x00];
+        config.quirks = Quirks::COSMAC_VIP;
+
+        cpu.v[0xF] = 0x01;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[0xF], 0x00);
+    }
+
     #[test]
     fn opcode_8xy2() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x22, 0x00, 0x00];
 
         cpu.v[0x2] = 0x34;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0x00);
 
         cpu.pc = 0;
         cpu.v[0x1] = 0x12;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0x10);
     }
 
     #[test]
     fn opcode_8xy3() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x23, 0x00, 0x00];
 
         cpu.v[0x1] = 0xA7;
         cpu.v[0x2] = 0x35;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0xA7 ^ 0x35);
     }
 
     #[test]
     fn opcode_8xy4() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x24, 0x00, 0x00];
 
         cpu.v[0x1] = 0x12;
         cpu.v[0x2] = 0x34;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0x12 + 0x34);
         assert!(cpu.v[0xF] != 0x01);
 
         cpu.pc = 0;
         cpu.v[0x1] = 150;
         cpu.v[0x2] = 106;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0);
         assert!(cpu.v[0xF] == 0x01);
     }
 
     #[test]
     fn opcode_8xy5() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x25, 0x00, 0x00];
 
         cpu.v[0x1] = 100;
         cpu.v[0x2] = 60;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 40);
         assert!(cpu.v[0xF] == 0x01);
 
         cpu.pc = 0;
         cpu.v[0x1] = 30;
         cpu.v[0x2] = 31;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 255);
         assert!(cpu.v[0xF] == 0x00);
     }
 
     #[test]
     fn opcode_8xy6() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x26, 0x00, 0x00];
 
         cpu.v[0x1] = 0b1000_1010;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0b0100_0101);
         assert!(cpu.v[0xF] == 0x00);
 
         cpu.pc = 0;
         cpu.v[0x1] = 0b1001_1011;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0b0100_1101);
         assert!(cpu.v[0xF] == 0x01);
     }
 
+    #[test]
+    fn opcode_8xy6_shift_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x81, 0x26, 0x00, 0x00];
+        config.quirks = Quirks::COSMAC_VIP;
+
+        cpu.v[0x1] = 0b1111_1111;
+        cpu.v[0x2] = 0b1000_1010;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[0x1], 0b0100_0101);
+        assert_eq!(cpu.v[0x2], 0b1000_1010);
+        assert!(cpu.v[0xF] == 0x00);
+    }
+
     #[test]
     fn opcode_8xy7() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x27, 0x00, 0x00];
 
         cpu.v[0x1] = 30;
         cpu.v[0x2] = 110;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 80);
         assert!(cpu.v[0xF] == 0x01);
 
         cpu.pc = 0;
         cpu.v[0x1] = 41;
         cpu.v[0x2] = 40;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 255);
         assert!(cpu.v[0xF] == 0x00);
     }
 
     #[test]
     fn opcode_8xye() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x81, 0x2e, 0x00, 0x00];
 
         cpu.v[0x1] = 0b0100_1010;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0b1001_0100);
         assert!(cpu.v[0xF] == 0x00);
 
         cpu.pc = 0;
         cpu.v[0x1] = 0b1001_1011;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[0x1], 0b0011_0110);
+        assert!(cpu.v[0xF] == 0x01);
+    }
+
+    #[test]
+    fn opcode_8xye_shift_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0x81, 0x2e, 0x00, 0x00];
+        config.quirks = Quirks::COSMAC_VIP;
+
+        cpu.v[0x1] = 0b0000_0000;
+        cpu.v[0x2] = 0b1001_1011;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 0b0011_0110);
         assert!(cpu.v[0xF] == 0x01);
     }
 
     #[test]
     fn opcode_9xy0() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0x91, 0x20, 0x00, 0x00];
 
         cpu.v[0x1] = 0x12;
         cpu.v[0x2] = 0x12;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == 2);
 
         cpu.pc = 0;
         cpu.v[0x1] = 0x12;
         cpu.v[0x2] = 0x22;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == 4);
     }
 
     #[test]
     fn opcode_annn() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xA1, 0x23, 0x00, 0x00];
 
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.i, 0x123);
     }
 
     #[test]
     fn opcode_bnnn() {
-        let (mut cpu, mut screen) = test_setup();
-        let mut memory: [u8; 4] = [0xB0, 0x23, 0x00, 0x00];
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory = [0; 0x1000];
+        memory[..2].copy_from_slice(&[0xB0, 0x23]);
 
         cpu.v[0x0] = 0x10;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == (0x10 + 0x23));
     }
 
+    #[test]
+    fn opcode_bxnn_jump_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory = [0; 0x1000];
+        memory[..2].copy_from_slice(&[0xB2, 0x23]);
+        config.quirks = Quirks::SCHIP_1_1;
+
+        cpu.v[0x0] = 0x10;
+        cpu.v[0x2] = 0x20;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert!(cpu.pc == (0x20 + 0x223));
+    }
+
+    #[test]
+    fn opcode_bnnn_out_of_bounds() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory = [0; 0x1000];
+        memory[..2].copy_from_slice(&[0xBF, 0xFF]);
+        cpu.v[0x0] = 0xFF;
+
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(
+            e,
+            Err(ChipError::AddressOutOfBounds {
+                address: 0x10FE,
+                limit: 0x1000
+            })
+        ));
+
+        config.out_of_bounds = OutOfBounds::Wrap;
+        cpu.pc = 0;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 0x0FE);
+    }
+
+    #[test]
+    fn fetch_out_of_bounds() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        // LD V0, 0x05 split across the end and the start of the memory
+        let mut memory: [u8; 4] = [0x05, 0x00, 0x00, 0x60];
+        cpu.pc = 3;
+
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(
+            e,
+            Err(ChipError::AddressOutOfBounds {
+                address: 4,
+                limit: 4
+            })
+        ));
+
+        config.out_of_bounds = OutOfBounds::Wrap;
+        cpu.pc = 3;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[0x0], 0x05);
+    }
+
+    #[test]
+    fn opcode_cxnn() {
+        let (mut cpu, mut screen, config, _) = test_setup();
+        let mut memory: [u8; 4] = [0xC1, 0x0F, 0x00, 0x00];
+
+        let mut rng = XorShiftRng::new(99);
+        let mut expected = XorShiftRng::new(99);
+        for _ in 0..8 {
+            cpu.pc = 0;
+            cpu.step(&mut memory, &mut screen, &config, &mut rng)
+                .unwrap();
+            assert_eq!(cpu.v[0x1], expected.next_u8() & 0x0F);
+        }
+    }
+
+    #[test]
+    fn opcode_dxyn() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xD0, 0x12, 0xC0, 0x81];
+
+        cpu.i = 2;
+        cpu.v[0x0] = 62;
+        cpu.v[0x1] = 31;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(62, 31), 1);
+        assert_eq!(screen.get_pixel(63, 31), 1);
+        assert_eq!(screen.get_pixel(62, 0), 1);
+        assert_eq!(screen.get_pixel(5, 0), 1);
+        assert_eq!(cpu.v[0xF], 0);
+
+        cpu.pc = 0;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(62, 31), 0);
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn opcode_dxyn_out_of_bounds() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xD0, 0x12, 0xC0, 0x81];
+
+        cpu.i = 3;
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(
+            e,
+            Err(ChipError::AddressOutOfBounds {
+                address: 4,
+                limit: 4
+            })
+        ));
+
+        cpu.pc = 0;
+        config.out_of_bounds = OutOfBounds::Wrap;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(0, 1), 1);
+        assert_eq!(screen.get_pixel(1, 1), 1);
+        assert_eq!(screen.get_pixel(3, 1), 1);
+    }
+
+    #[test]
+    fn opcode_dxyn_clip_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xD0, 0x12, 0xC0, 0x81];
+        config.quirks = Quirks::COSMAC_VIP;
+
+        cpu.i = 2;
+        cpu.v[0x0] = 62;
+        cpu.v[0x1] = 31;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(62, 31), 1);
+        assert_eq!(screen.get_pixel(63, 31), 1);
+        assert_eq!(screen.get_pixel(62, 0), 0);
+        assert_eq!(screen.get_pixel(5, 0), 0);
+
+        // The starting position wraps around even when clipping
+        cpu.pc = 0;
+        cpu.v[0x0] = 64 + 2;
+        cpu.v[0x1] = 32 + 2;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(2, 2), 1);
+    }
+
+    #[test]
+    fn opcode_dxy0() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory = [0xFF; 34];
+        memory[0] = 0xD0;
+        memory[1] = 0x10;
+
+        screen.set_hires(true);
+        cpu.i = 2;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(0, 0), 1);
+        assert_eq!(screen.get_pixel(15, 15), 1);
+        assert_eq!(screen.get_pixel(16, 15), 0);
+        assert_eq!(screen.get_pixel(15, 16), 0);
+        assert_eq!(cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn opcode_dxyn_planes() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 4] = [0xD0, 0x11, 0x80, 0xC0];
+
+        cpu.i = 2;
+        screen.select_planes(0b11);
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(0, 0), 0b11);
+        assert_eq!(screen.get_pixel(1, 0), 0b10);
+        assert_eq!(cpu.v[0xF], 0);
+
+        cpu.pc = 0;
+        screen.select_planes(0b10);
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.get_pixel(0, 0), 0b01);
+        assert_eq!(screen.get_pixel(1, 0), 0b10);
+        assert_eq!(cpu.v[0xF], 1);
+    }
+
     #[test]
     fn opcode_ex9e() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xE1, 0x9E, 0x00, 0x00];
 
         cpu.v[0x1] = 0xA;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == 2);
 
         cpu.pc = 0;
         cpu.keypad[0xA] = true;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert!(cpu.pc == 4);
+    }
+
+    #[test]
+    fn opcode_ex9e_invalid_key() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xE1, 0x9E, 0x00, 0x00];
+
+        cpu.v[0x1] = 0x1A;
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(e, Err(ChipError::InvalidKey(0x1A))));
+
+        cpu.pc = 0;
+        cpu.keypad[0xA] = true;
+        config.out_of_bounds = OutOfBounds::Wrap;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == 4);
     }
 
     #[test]
     fn opcode_exa1() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xE1, 0xA1, 0x00, 0x00];
 
         cpu.v[0x1] = 0xA;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == 4);
 
         cpu.pc = 0;
         cpu.keypad[0xA] = true;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert!(cpu.pc == 2);
     }
 
+    #[test]
+    fn opcode_f000_nnnn() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 8] = [0xF0, 0x00, 0xAB, 0xCD, 0x30, 0x00, 0xF0, 0x00];
+
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.i, 0xABCD);
+        assert_eq!(cpu.pc, 4);
+
+        // Skipping over a long load skips all 4 bytes
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 10);
+
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(e, Err(ChipError::OpcodeNotImplemented { .. })));
+    }
+
+    #[test]
+    fn opcode_fn01() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 4] = [0xF2, 0x01, 0x00, 0x00];
+
+        assert_eq!(screen.selected_planes(), 0b01);
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(screen.selected_planes(), 0b10);
+    }
+
+    #[test]
+    fn opcode_f002() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory = [0; 20];
+        memory[..2].copy_from_slice(&[0xF0, 0x02]);
+        memory[4..].copy_from_slice(&[0xAA; 16]);
+
+        assert_eq!(cpu.pattern, None);
+        cpu.i = 4;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pattern, Some([0xAA; 16]));
+        assert_eq!(cpu.i, 4);
+    }
+
+    #[test]
+    fn opcode_fx3a() {
+        let (mut cpu, mut screen, config, mut rng) = xo_chip_setup();
+        let mut memory: [u8; 4] = [0xF3, 0x3A, 0x00, 0x00];
+
+        assert_eq!(cpu.pitch, 64);
+        cpu.v[0x3] = 112;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pitch, 112);
+    }
+
     #[test]
     fn opcode_fx07() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xF1, 0x07, 0x00, 0x00];
 
         cpu.timer_delay = 5;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0x1], 5);
     }
 
     #[test]
     fn opcode_fx0a() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xF1, 0x0A, 0x00, 0x00];
 
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 0);
 
         cpu.keypad[0x2] = true;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.pc, 2);
     }
 
+    #[test]
+    fn opcode_fx0a_press() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        config.quirks.key_wait = KeyWait::Press;
+        let mut memory: [u8; 4] = [0xF1, 0x0A, 0x00, 0x00];
+
+        // A key held since before the wait doesn't count
+        cpu.keypad[0x3] = true;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 0);
+
+        // Pressed and released before the instruction runs
+        cpu.key_down(0x7).unwrap();
+        cpu.key_up(0x7).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.v[0x1], 0x7);
+    }
+
+    #[test]
+    fn opcode_fx0a_release() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        config.quirks.key_wait = KeyWait::Release;
+        let mut memory: [u8; 4] = [0xF1, 0x0A, 0x00, 0x00];
+
+        cpu.key_down(0x5).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 0);
+
+        // Other keys are ignored until the pressed one is released
+        cpu.key_down(0x6).unwrap();
+        cpu.key_up(0x6).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 0);
+
+        cpu.key_up(0x5).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.v[0x1], 0x5);
+        let e = cpu.key_down(0x10);
+        assert!(matches!(e, Err(ChipError::InvalidKey(0x10))));
+    }
+
     #[test]
     fn opcode_fx15() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xF1, 0x15, 0x00, 0x00];
 
         cpu.v[1] = 10;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.timer_delay, 10);
     }
 
     #[test]
     fn opcode_fx18() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xF1, 0x18, 0x00, 0x00];
 
         cpu.v[1] = 15;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.timer_sound, 15);
     }
 
     #[test]
     fn opcode_fx1e() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 4] = [0xF1, 0x1E, 0x00, 0x00];
 
         cpu.i = 40;
         cpu.v[0x1] = 60;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.i, 100);
     }
 
+    #[test]
+    fn opcode_fx1e_overflow() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xF1, 0x1E, 0x00, 0x00];
+
+        cpu.i = 0xFFF0;
+        cpu.v[0x1] = 0x20;
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(e, Err(ChipError::AddressOutOfBounds { .. })));
+        assert_eq!(cpu.i, 0xFFF0);
+
+        cpu.pc = 0;
+        config.out_of_bounds = OutOfBounds::Wrap;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.i, 0x10);
+    }
+
+    #[test]
+    fn opcode_fx30() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xF1, 0x30, 0x00, 0x00];
+
+        cpu.v[0x1] = 2;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.i as usize, BIG_FONT_BASE_ADDRESS + 20);
+    }
+
     #[test]
     fn opcode_fx33() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 5] = [0xF0, 0x33, 0x00, 0x00, 0x00];
 
         cpu.v[0] = 123;
         cpu.i = 0x002;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(memory[0x002], 0x01);
         assert_eq!(memory[0x003], 0x02);
         assert_eq!(memory[0x004], 0x03);
@@ -667,7 +1496,8 @@ mod tests {
         cpu.v[0] = 0x97;
         cpu.i = 0x002;
         cpu.pc = 0;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(memory[0x002], 0x01);
         assert_eq!(memory[0x003], 0x05);
         assert_eq!(memory[0x004], 0x01);
@@ -675,31 +1505,109 @@ mod tests {
 
     #[test]
     fn opcode_fx55() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 5] = [0xF2, 0x55, 0x00, 0x00, 0x00];
 
         cpu.i = 2;
         cpu.v[0] = 0x12;
         cpu.v[1] = 0x34;
         cpu.v[2] = 0x56;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(memory[2], 0x12);
         assert_eq!(memory[3], 0x34);
         assert_eq!(memory[4], 0x56);
     }
 
+    #[test]
+    fn opcode_fx55_out_of_bounds() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 5] = [0xF2, 0x55, 0x00, 0x00, 0x00];
+
+        cpu.i = 3;
+        cpu.v[0] = 0x12;
+        cpu.v[1] = 0x34;
+        cpu.v[2] = 0x56;
+        let e = cpu.step(&mut memory, &mut screen, &config, &mut rng);
+        assert!(matches!(
+            e,
+            Err(ChipError::AddressOutOfBounds {
+                address: 5,
+                limit: 5
+            })
+        ));
+
+        cpu.pc = 0;
+        config.out_of_bounds = OutOfBounds::Wrap;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(memory, [0x56, 0x55, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn opcode_fx55_index_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 5] = [0xF2, 0x55, 0x00, 0x00, 0x00];
+
+        config.quirks = Quirks::COSMAC_VIP;
+        cpu.i = 2;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.i, 5);
+
+        config.quirks = Quirks::CHIP_48;
+        cpu.pc = 0;
+        cpu.i = 2;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.i, 4);
+    }
+
     #[test]
     fn opcode_fx65() {
-        let (mut cpu, mut screen) = test_setup();
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
         let mut memory: [u8; 5] = [0xF2, 0x65, 0x01, 0x02, 0x03];
 
         cpu.i = 0x002;
-        cpu.step(&mut memory, &mut screen).unwrap();
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
         assert_eq!(cpu.v[0], 0x01);
         assert_eq!(cpu.v[1], 0x02);
         assert_eq!(cpu.v[2], 0x03);
     }
 
+    #[test]
+    fn opcode_fx65_index_quirk() {
+        let (mut cpu, mut screen, mut config, mut rng) = test_setup();
+        let mut memory: [u8; 5] = [0xF2, 0x65, 0x01, 0x02, 0x03];
+        config.quirks = Quirks::XO_CHIP;
+
+        cpu.i = 0x002;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[2], 0x03);
+        assert_eq!(cpu.i, 0x005);
+    }
+
+    #[test]
+    fn opcode_fx75_fx85() {
+        let (mut cpu, mut screen, config, mut rng) = test_setup();
+        let mut memory: [u8; 4] = [0xF2, 0x75, 0xF2, 0x85];
+
+        cpu.v[0] = 0x12;
+        cpu.v[1] = 0x34;
+        cpu.v[2] = 0x56;
+        cpu.v[3] = 0x78;
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.rpl[..4], [0x12, 0x34, 0x56, 0x00]);
+
+        cpu.v = [0; 16];
+        cpu.step(&mut memory, &mut screen, &config, &mut rng)
+            .unwrap();
+        assert_eq!(cpu.v[..4], [0x12, 0x34, 0x56, 0x00]);
+    }
+
     #[test]
     fn test_bcd() {
         let (bcd2, bcd1, bcd0) = bcd(123);
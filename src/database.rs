@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use crate::json::Json;
+use crate::sha1::sha1;
+use crate::{Chip8, ChipError, Config, IndexIncrement, KeyWait, Keymap, Palette, Platform, Quirks};
+
+// The host keys the named keys of the database are bound to, on top of the
+// COSMAC VIP layout
+const NAMED_KEYS: [(&str, &[&str]); 12] = [
+    ("up", &["ArrowUp", "DPadUp"]),
+    ("down", &["ArrowDown", "DPadDown"]),
+    ("left", &["ArrowLeft", "DPadLeft"]),
+    ("right", &["ArrowRight", "DPadRight"]),
+    ("a", &["Space", "South"]),
+    ("b", &["Enter", "East"]),
+    ("player2Up", &["KeyI"]),
+    ("player2Down", &["KeyK"]),
+    ("player2Left", &["KeyJ"]),
+    ("player2Right", &["KeyL"]),
+    ("player2A", &["KeyN"]),
+    ("player2B", &["KeyM"]),
+];
+
+/// What the [RomDatabase] knows about a ROM.
+#[derive(Clone, Debug)]
+pub struct RomInfo {
+    /// The title of the program.
+    pub title: String,
+    /// The authors of the program.
+    pub authors: Vec<String>,
+    /// The identifier the database uses for the platform the ROM is run on, like
+    /// `originalChip8`, `superchip` or `xochip`.
+    pub platform: String,
+    /// The platform, quirks, tick rate and start address recommended for the ROM,
+    /// with the defaults for everything else.
+    pub config: Config,
+    /// The keys the ROM uses bound to the arrows, Space and Enter, and to the D-pad
+    /// and face buttons, on top of the COSMAC VIP layout.
+    pub keymap: Option<Keymap>,
+    /// The colors the ROM was designed for.
+    pub palette: Option<Palette>,
+}
+
+/// ROM metadata looked up by the SHA-1 of the ROM, read from the `programs.json`
+/// file of the [CHIP-8 database](https://github.com/chip-8/chip-8-database).
+///
+/// The crate doesn't bundle the database, embed the file in the frontend with
+/// `include_str!` or load it at runtime. ROMs for platforms the interpreter can't
+/// run, like MegaChip or the hybrid VIP programs mixing in 1802 machine code, are
+/// left out.
+#[derive(Clone, Debug, Default)]
+pub struct RomDatabase {
+    roms: HashMap<[u8; 20], RomInfo>,
+}
+
+impl RomDatabase {
+    /// Read the programs of the database.
+    pub fn from_json(programs: &str) -> Result<Self, ChipError> {
+        let json = Json::parse(programs).map_err(ChipError::InvalidDatabase)?;
+        let programs = json
+            .as_array()
+            .ok_or_else(|| invalid("expected an array of programs"))?;
+
+        let mut roms = HashMap::new();
+        for program in programs {
+            let title = program
+                .get("title")
+                .and_then(Json::as_str)
+                .ok_or_else(|| invalid("program without a title"))?;
+            let authors: Vec<String> = program
+                .get("authors")
+                .and_then(Json::as_array)
+                .unwrap_or(&[])
+                .iter()
+                .filter_map(|author| author.as_str().map(str::to_string))
+                .collect();
+            let files = program
+                .get("roms")
+                .and_then(Json::as_object)
+                .ok_or_else(|| invalid(&format!("'{}' has no ROMs", title)))?;
+
+            for (hash, rom) in files {
+                let hash = parse_hash(hash)
+                    .ok_or_else(|| invalid(&format!("invalid SHA-1 '{}' in '{}'", hash, title)))?;
+                let Some((platform, config)) = rom_config(rom) else {
+                    continue;
+                };
+
+                roms.insert(
+                    hash,
+                    RomInfo {
+                        title: title.to_string(),
+                        authors: authors.clone(),
+                        platform,
+                        config,
+                        keymap: rom_keymap(rom),
+                        palette: rom_palette(rom),
+                    },
+                );
+            }
+        }
+
+        Ok(RomDatabase { roms })
+    }
+
+    /// The metadata of the ROM, if it is in the database.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&sha1(rom))
+    }
+
+    /// How many ROMs are in the database.
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    /// Whether the database holds no ROM.
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+impl Chip8 {
+    /// Load the ROM the way [`Chip8::load_rom`] does, after resetting the machine with
+    /// the platform, quirks, tick rate and start address the database recommends
+    /// for it. The rest of the [Config] is kept. Returns what the database knows
+    /// about the ROM, or None when it isn't in the database and the [Config] is left
+    /// as it was.
+    pub fn load_rom_from_database(
+        &mut self,
+        rom: &[u8],
+        database: &RomDatabase,
+    ) -> Result<Option<RomInfo>, ChipError> {
+        let info = database.lookup(rom).cloned();
+        if let Some(info) = &info {
+            self.config.platform = info.config.platform;
+            self.config.quirks = info.config.quirks;
+            self.config.tick_rate = info.config.tick_rate;
+            self.config.rom_base_addr = info.config.rom_base_addr;
+            // Don't reload the previous ROM, it might not fit in the new memory
+            self.rom.clear();
+            self.reset();
+        }
+        self.load_rom(rom)?;
+
+        Ok(info)
+    }
+}
+
+fn invalid(message: &str) -> ChipError {
+    ChipError::InvalidDatabase(message.to_string())
+}
+
+fn parse_hash(hash: &str) -> Option<[u8; 20]> {
+    if hash.len() != 40 || !hash.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 20];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+// The quirks the platforms of the database have unless a ROM says otherwise
+fn platform_quirks(platform: &str) -> Option<(Platform, Quirks)> {
+    match platform {
+        "originalChip8" => Some((Platform::Chip8, Quirks::COSMAC_VIP)),
+        "modernChip8" => Some((
+            Platform::Chip8,
+            Quirks {
+                shift_uses_vy: true,
+                load_store_index: IndexIncrement::ByXPlusOne,
+                jump_uses_vx: false,
+                logic_resets_vf: false,
+                clip_sprites: true,
+                key_wait: KeyWait::Release,
+            },
+        )),
+        "chip48" => Some((Platform::Chip8, Quirks::CHIP_48)),
+        "superchip1" => Some((Platform::Chip8, Quirks::SCHIP_1_0)),
+        "superchip" => Some((Platform::Chip8, Quirks::SCHIP_1_1)),
+        "xochip" => Some((Platform::XoChip, Quirks::XO_CHIP)),
+        _ => None,
+    }
+}
+
+// The first platform of the ROM the interpreter supports, with its quirks changed
+// by the ones the ROM lists for it. The vblank quirk has no equivalent here.
+fn rom_config(rom: &Json) -> Option<(String, Config)> {
+    let (id, platform, mut quirks) = rom
+        .get("platforms")?
+        .as_array()?
+        .iter()
+        .filter_map(Json::as_str)
+        .find_map(|id| platform_quirks(id).map(|(p, q)| (id, p, q)))?;
+
+    let flags = rom.get("quirkyPlatforms").and_then(|q| q.get(id));
+    let flag = |name: &str| flags.and_then(|f| f.get(name)).and_then(Json::as_bool);
+    if let Some(shift) = flag("shift") {
+        quirks.shift_uses_vy = !shift;
+    }
+    let leave_i = flag("memoryLeaveIUnchanged");
+    let by_x = flag("memoryIncrementByX");
+    if leave_i.is_some() || by_x.is_some() {
+        let leave_i = leave_i.unwrap_or(quirks.load_store_index == IndexIncrement::Unchanged);
+        let by_x = by_x.unwrap_or(quirks.load_store_index == IndexIncrement::ByX);
+        quirks.load_store_index = match (leave_i, by_x) {
+            (true, _) => IndexIncrement::Unchanged,
+            (false, true) => IndexIncrement::ByX,
+            (false, false) => IndexIncrement::ByXPlusOne,
+        };
+    }
+    if let Some(wrap) = flag("wrap") {
+        quirks.clip_sprites = !wrap;
+    }
+    if let Some(jump) = flag("jump") {
+        quirks.jump_uses_vx = jump;
+    }
+    if let Some(logic) = flag("logic") {
+        quirks.logic_resets_vf = logic;
+    }
+
+    let mut config = Config {
+        platform,
+        quirks,
+        ..Default::default()
+    };
+    if let Some(tick_rate) = rom.get("tickrate").and_then(Json::as_f64) {
+        config.tick_rate = tick_rate as u32;
+    }
+    if let Some(address) = rom.get("startAddress").and_then(Json::as_f64) {
+        config.rom_base_addr = address as usize;
+    }
+
+    Some((id.to_string(), config))
+}
+
+fn rom_keymap(rom: &Json) -> Option<Keymap> {
+    let keys = rom.get("keys")?;
+    let mut keymap = Keymap::default();
+    for (name, hosts) in NAMED_KEYS {
+        let Some(key) = keys.get(name).and_then(Json::as_f64) else {
+            continue;
+        };
+        for host in hosts {
+            // Keys outside of the keypad are ignored
+            let _ = keymap.bind(host, key as u8);
+        }
+    }
+
+    Some(keymap)
+}
+
+fn rom_palette(rom: &Json) -> Option<Palette> {
+    let colors: Vec<u32> = rom
+        .get("colors")?
+        .get("pixels")?
+        .as_array()?
+        .iter()
+        .map(|color| {
+            let hex = color.as_str()?.strip_prefix('#')?;
+            u32::from_str_radix(hex, 16).ok()
+        })
+        .collect::<Option<_>>()?;
+
+    Palette::from_colors(&colors).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 of the ROM in hex
+    fn hex_hash(rom: &[u8]) -> String {
+        sha1(rom).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn database(roms: &[(&[u8], &str)]) -> RomDatabase {
+        let entries: Vec<String> = roms
+            .iter()
+            .map(|(rom, entry)| format!("\"{}\": {}", hex_hash(rom), entry))
+            .collect();
+        let json = format!(
+            r#"[{{ "title": "Test", "authors": ["A", "B"], "roms": {{ {} }} }}]"#,
+            entries.join(",")
+        );
+
+        RomDatabase::from_json(&json).unwrap()
+    }
+
+    #[test]
+    fn lookup() {
+        let schip = [0x00, 0xFF, 0x12, 0x02];
+        let xo = [0xF0, 0x00, 0x12, 0x00];
+        let mega = [0x00, 0x11];
+        let hybrid = [0x02, 0x00];
+        let db = database(&[
+            (
+                &schip,
+                r##"{ "platforms": ["megachip8", "superchip"], "tickrate": 30,
+                     "quirkyPlatforms": { "superchip": { "wrap": true, "memoryLeaveIUnchanged": false, "memoryIncrementByX": true } },
+                     "keys": { "up": 5, "a": 6, "b": 99 },
+                     "colors": { "pixels": ["#101010", "#F0F0F0"] } }"##,
+            ),
+            (&xo, r#"{ "platforms": ["xochip"], "startAddress": 768 }"#),
+            (&mega, r#"{ "platforms": ["megachip8"] }"#),
+            (&hybrid, r#"{ "platforms": ["hybridVIP"] }"#),
+        ]);
+        assert_eq!(db.len(), 2);
+        assert!(db.lookup(&mega).is_none());
+        assert!(db.lookup(&hybrid).is_none());
+
+        let info = db.lookup(&schip).unwrap();
+        assert_eq!(info.title, "Test");
+        assert_eq!(info.authors, ["A", "B"]);
+        assert_eq!(info.platform, "superchip");
+        assert_eq!(info.config.tick_rate, 30);
+        assert!(!info.config.quirks.clip_sprites);
+        assert_eq!(info.config.quirks.load_store_index, IndexIncrement::ByX);
+        assert!(info.config.quirks.jump_uses_vx);
+        let keymap = info.keymap.as_ref().unwrap();
+        assert_eq!(keymap.key("ArrowUp"), Some(0x5));
+        assert_eq!(keymap.key("South"), Some(0x6));
+        assert_eq!(keymap.key("Enter"), None);
+        assert_eq!(keymap.key("KeyQ"), Some(0x4));
+        assert_eq!(info.palette, Some(Palette::new(0x101010, 0xF0F0F0)));
+
+        let info = db.lookup(&xo).unwrap();
+        assert_eq!(info.config.platform, Platform::XoChip);
+        assert_eq!(info.config.quirks, Quirks::XO_CHIP);
+        assert_eq!(info.config.rom_base_addr, 0x300);
+        assert!(info.keymap.is_none() && info.palette.is_none());
+
+        let e = RomDatabase::from_json(r#"[{ "title": "x", "roms": { "abc": {} } }]"#);
+        assert!(matches!(e, Err(ChipError::InvalidDatabase(_))));
+    }
+
+    #[test]
+    fn load_from_database() {
+        let xo = [0xF0, 0x00, 0x12, 0x00];
+        let db = database(&[(&xo, r#"{ "platforms": ["xochip"], "tickrate": 100 }"#)]);
+
+        let mut c8 = Chip8::new(Config {
+            seed: Some(7),
+            ..Default::default()
+        });
+        let info = c8.load_rom_from_database(&xo, &db).unwrap();
+        assert_eq!(info.unwrap().title, "Test");
+        assert_eq!(c8.memory.len(), 65536);
+        assert_eq!(c8.memory[0x200..0x204], xo);
+        assert_eq!(c8.config.tick_rate, 100);
+        assert_eq!(c8.config.seed, Some(7));
+
+        // Unknown ROMs keep the configuration
+        let info = c8.load_rom_from_database(&[0x12, 0x00], &db).unwrap();
+        assert!(info.is_none());
+        assert_eq!(c8.config.platform, Platform::XoChip);
+        assert_eq!(c8.memory[0x200..0x202], [0x12, 0x00]);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::cpu::{AccessKind, MemoryAccess};
+use crate::{Chip8, ChipError, Cpu};
+
+/// The reason why [`Chip8::tick`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// All the cycles of the frame ran and the timers were updated.
+    FrameComplete,
+    /// Execution stopped before running the instruction at this address.
+    Breakpoint(usize),
+    /// Execution stopped after an instruction accessed a watched address.
+    Watchpoint(MemoryAccess),
+    /// A step over or step out finished.
+    StepComplete,
+    /// The ROM exited with the Super-Chip 00FD instruction.
+    Halted,
+}
+
+/// A value of the [Cpu] that a [Condition] can inspect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    /// One of the V0 - VF general purpose registers.
+    V(u8),
+    I,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+}
+
+/// How a [Condition] compares the register to its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+}
+
+/// A check on the value of a register which must hold for a conditional
+/// breakpoint to stop execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn new(register: Register, comparison: Comparison, value: u16) -> Self {
+        Condition {
+            register,
+            comparison,
+            value,
+        }
+    }
+
+    /// Whether the condition holds for the current state of the [Cpu].
+    pub fn is_met(&self, cpu: &Cpu) -> bool {
+        let current = match self.register {
+            Register::V(reg) => cpu.v[(reg & 0xF) as usize] as u16,
+            Register::I => cpu.i,
+            Register::Sp => cpu.sp as u16,
+            Register::DelayTimer => cpu.timer_delay as u16,
+            Register::SoundTimer => cpu.timer_sound as u16,
+        };
+
+        match self.comparison {
+            Comparison::Equal => current == self.value,
+            Comparison::NotEqual => current != self.value,
+            Comparison::LessThan => current < self.value,
+            Comparison::GreaterThan => current > self.value,
+        }
+    }
+}
+
+/// Which kind of memory access triggers a watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(&self, kind: AccessKind) -> bool {
+        matches!(
+            (self, kind),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, AccessKind::Read)
+                | (WatchKind::Write, AccessKind::Write)
+        )
+    }
+}
+
+/// A range of memory addresses being watched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: Range<usize>,
+    pub kind: WatchKind,
+}
+
+// A step over or step out waiting for a subroutine to return
+#[derive(Clone, Copy, Debug)]
+enum StepTarget {
+    Return { pc: usize, sp: usize },
+    Out { sp: usize },
+}
+
+/// Controls where [`Chip8::tick`] stops the execution.
+///
+/// Breakpoints are checked before an instruction runs and watchpoints after it
+/// ran. When [`Chip8::tick`] is called again after stopping at a breakpoint, the
+/// instruction at the breakpoint is executed instead of stopping again.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    target: Option<StepTarget>,
+    resume_pc: Option<usize>,
+}
+
+impl Debugger {
+    /// Stop before the instruction at the address runs.
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address, None);
+    }
+
+    /// Stop before the instruction at the address runs only if the condition holds.
+    pub fn add_conditional_breakpoint(&mut self, address: usize, condition: Condition) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    /// Remove the breakpoint at the address. Returns false if there was none.
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    /// Remove all the breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// The addresses of the breakpoints along with their condition, in ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, Option<&Condition>)> {
+        self.breakpoints
+            .iter()
+            .map(|(address, condition)| (*address, condition.as_ref()))
+    }
+
+    /// Stop after an instruction accesses any address in the range.
+    pub fn add_watchpoint(&mut self, range: Range<usize>, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { range, kind });
+    }
+
+    /// Remove the watchpoints covering exactly this range. Returns false if there was none.
+    pub fn remove_watchpoint(&mut self, range: Range<usize>) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.range != range);
+
+        count != self.watchpoints.len()
+    }
+
+    /// Remove all the watchpoints.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// All the watchpoints in the order they were added.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Whether a step over or step out is waiting for a subroutine to return.
+    pub fn is_stepping(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Cancel a pending step over or step out.
+    pub fn cancel_step(&mut self) {
+        self.target = None;
+    }
+
+    pub(crate) fn check_before(&mut self, cpu: &Cpu) -> Option<StopReason> {
+        if self.resume_pc.take() == Some(cpu.pc) {
+            return None;
+        }
+
+        let hit = match self.breakpoints.get(&cpu.pc) {
+            Some(Some(condition)) => condition.is_met(cpu),
+            Some(None) => true,
+            None => false,
+        };
+
+        match hit {
+            true => {
+                self.target = None;
+                self.resume_pc = Some(cpu.pc);
+                Some(StopReason::Breakpoint(cpu.pc))
+            }
+            false => None,
+        }
+    }
+
+    pub(crate) fn check_after(&mut self, cpu: &Cpu) -> Option<StopReason> {
+        let watched = cpu.memory_accesses().iter().find(|access| {
+            self.watchpoints
+                .iter()
+                .any(|w| w.range.contains(&access.address) && w.kind.matches(access.kind))
+        });
+        if let Some(access) = watched {
+            self.target = None;
+            return Some(StopReason::Watchpoint(*access));
+        }
+
+        let finished = match self.target {
+            Some(StepTarget::Return { pc, sp }) => cpu.pc == pc && cpu.sp == sp,
+            Some(StepTarget::Out { sp }) => cpu.sp < sp,
+            None => false,
+        };
+
+        match finished {
+            true => {
+                self.target = None;
+                Some(StopReason::StepComplete)
+            }
+            false => None,
+        }
+    }
+}
+
+impl Chip8 {
+    /// Execute the next instruction. If it calls a subroutine (2NNN), the following
+    /// calls to [`Chip8::tick`] run until the subroutine returns and then stop with
+    /// [`StopReason::StepComplete`].
+    ///
+    /// Returns true when the step already completed.
+    pub fn step_over(&mut self) -> Result<bool, ChipError> {
+        let pc = self.cpu.pc;
+        let sp = self.cpu.sp;
+        let is_call = self.memory.get(pc).is_some_and(|op| op & 0xF0 == 0x20);
+
+        self.debugger.resume_pc = None;
+        self.step()?;
+
+        match is_call && !self.cpu.halted {
+            true => {
+                self.debugger.target = Some(StepTarget::Return { pc: pc + 2, sp });
+                Ok(false)
+            }
+            false => Ok(true),
+        }
+    }
+
+    /// Make the following calls to [`Chip8::tick`] run until the current subroutine
+    /// returns and then stop with [`StopReason::StepComplete`].
+    ///
+    /// Returns true without doing anything when not inside a subroutine.
+    pub fn step_out(&mut self) -> bool {
+        match self.cpu.sp {
+            0 => true,
+            sp => {
+                self.debugger.target = Some(StepTarget::Out { sp });
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(rom: &[u8]) -> Chip8 {
+        let mut c8 = Chip8::default();
+        c8.load_rom(rom).unwrap();
+
+        c8
+    }
+
+    #[test]
+    fn breakpoint() {
+        // 0x200: LD V0, 1 ; 0x202: ADD V0, 1 ; 0x204: JP 0x202
+        let mut c8 = setup(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        c8.debugger.add_breakpoint(0x204);
+
+        assert_eq!(c8.tick().unwrap(), StopReason::Breakpoint(0x204));
+        assert_eq!(c8.cpu.v[0], 2);
+
+        // Resuming executes the instruction at the breakpoint
+        assert_eq!(c8.tick().unwrap(), StopReason::Breakpoint(0x204));
+        assert_eq!(c8.cpu.v[0], 3);
+
+        assert!(c8.debugger.remove_breakpoint(0x204));
+        assert_eq!(c8.tick().unwrap(), StopReason::FrameComplete);
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mut c8 = setup(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        let condition = Condition::new(Register::V(0), Comparison::Equal, 5);
+        c8.debugger.add_conditional_breakpoint(0x204, condition);
+
+        assert_eq!(c8.tick().unwrap(), StopReason::Breakpoint(0x204));
+        assert_eq!(c8.cpu.v[0], 5);
+    }
+
+    #[test]
+    fn watchpoint() {
+        // 0x200: LD I, 0x300 ; 0x202: LD [I], V0 ; 0x204: LD V0, [I]
+        let mut c8 = setup(&[0xA3, 0x00, 0xF0, 0x55, 0xF0, 0x65]);
+        c8.debugger.add_watchpoint(0x300..0x301, WatchKind::Read);
+
+        let reason = c8.tick().unwrap();
+        assert!(matches!(
+            reason,
+            StopReason::Watchpoint(MemoryAccess {
+                address: 0x300,
+                kind: AccessKind::Read,
+                ..
+            })
+        ));
+        assert_eq!(c8.cpu.pc, 0x206);
+
+        c8.reset();
+        c8.debugger.clear_watchpoints();
+        c8.debugger.add_watchpoint(0x2FF..0x301, WatchKind::Write);
+        let reason = c8.tick().unwrap();
+        assert!(matches!(reason, StopReason::Watchpoint(_)));
+        assert_eq!(c8.cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn step_over() {
+        // 0x200: CALL 0x206 ; 0x202: LD V1, 1 ; 0x204: JP 0x204
+        // 0x206: LD V0, 2 ; 0x208: RET
+        let mut c8 = setup(&[0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x02, 0x00, 0xEE]);
+
+        assert!(!c8.step_over().unwrap());
+        assert_eq!(c8.tick().unwrap(), StopReason::StepComplete);
+        assert_eq!(c8.cpu.pc, 0x202);
+        assert_eq!(c8.cpu.v[0], 2);
+
+        assert!(c8.step_over().unwrap());
+        assert_eq!(c8.cpu.v[1], 1);
+    }
+
+    #[test]
+    fn step_out() {
+        let mut c8 = setup(&[0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x02, 0x00, 0xEE]);
+
+        assert!(c8.step_out());
+        c8.step().unwrap();
+        assert!(!c8.step_out());
+        assert_eq!(c8.tick().unwrap(), StopReason::StepComplete);
+        assert_eq!(c8.cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn halted() {
+        let mut c8 = setup(&[0x00, 0xFD]);
+        c8.cpu.timer_delay = 2;
+
+        assert_eq!(c8.tick().unwrap(), StopReason::Halted);
+        assert_eq!(c8.cpu.timer_delay, 1);
+    }
+}
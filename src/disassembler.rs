@@ -0,0 +1,229 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::cpu::Opcode;
+use crate::{Chip8, Config, Platform};
+
+/// A decoded instruction from a disassembly listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    /// The address of the first byte of the instruction.
+    pub address: usize,
+    /// The raw bytes of the instruction. Usually 2 bytes, 4 for the XO-CHIP
+    /// F000 NNNN instruction and 1 for a trailing odd byte.
+    pub bytes: Vec<u8>,
+    /// The human readable form of the instruction, such as `LD V1, 0x23`.
+    pub mnemonic: String,
+}
+
+impl Instruction {
+    /// How many bytes the instruction takes up in memory.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:#06X}  {:<8}  {}", self.address, hex, self.mnemonic)
+    }
+}
+
+/// Decode the instruction at the start of the bytes, which are located at the
+/// address provided. The platform and quirks of the config decide how ambiguous
+/// opcodes are shown.
+pub fn disassemble_instruction(bytes: &[u8], address: usize, config: &Config) -> Instruction {
+    if bytes.len() < 2 {
+        return Instruction {
+            address,
+            bytes: bytes.to_vec(),
+            mnemonic: bytes
+                .first()
+                .map_or(String::new(), |b| format!("DB {:#04X}", b)),
+        };
+    }
+
+    let opcode = Opcode::from((bytes[0] as u16) << 8 | bytes[1] as u16);
+    if opcode.hex == 0xF000 && config.platform == Platform::XoChip && bytes.len() >= 4 {
+        let operand = (bytes[2] as u16) << 8 | bytes[3] as u16;
+        return Instruction {
+            address,
+            bytes: bytes[..4].to_vec(),
+            mnemonic: format!("LD I, {:#06X}", operand),
+        };
+    }
+
+    Instruction {
+        address,
+        bytes: bytes[..2].to_vec(),
+        mnemonic: mnemonic(&opcode, config),
+    }
+}
+
+/// Decode a block of bytes, such as a ROM, which is located at the base address.
+pub fn disassemble(bytes: &[u8], base_address: usize, config: &Config) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let instruction = disassemble_instruction(&bytes[offset..], base_address + offset, config);
+        offset += instruction.size();
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+impl Chip8 {
+    /// Decode the instructions in a range of memory using the platform and quirks of the [Config].
+    /// The range is limited to the size of the memory.
+    pub fn disassemble(&self, range: Range<usize>) -> Vec<Instruction> {
+        let end = range.end.min(self.memory.len());
+        let start = range.start.min(end);
+
+        disassemble(&self.memory[start..end], start, &self.config)
+    }
+}
+
+pub(crate) fn mnemonic(opcode: &Opcode, config: &Config) -> String {
+    let xo_chip = config.platform == Platform::XoChip;
+    let (x, y, n, nn, nnn) = (opcode.x, opcode.y, opcode.n, opcode.nn, opcode.nnn);
+    // BNNN jumps relative to VX instead of V0 with the quirk
+    let jump_register = match config.quirks.jump_uses_vx {
+        true => x,
+        false => 0,
+    };
+
+    match (opcode.prefix, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, 0x0, 0xC, _) => format!("SCD {}", n),
+        (0x0, 0x0, 0xD, _) if xo_chip => format!("SCU {}", n),
+        (0x0, 0x0, 0xF, 0xB) => "SCR".to_string(),
+        (0x0, 0x0, 0xF, 0xC) => "SCL".to_string(),
+        (0x0, 0x0, 0xF, 0xD) => "EXIT".to_string(),
+        (0x0, 0x0, 0xF, 0xE) => "LOW".to_string(),
+        (0x0, 0x0, 0xF, 0xF) => "HIGH".to_string(),
+        (0x0, _, _, _) => format!("SYS {:#05X}", nnn),
+        (0x1, _, _, _) => format!("JP {:#05X}", nnn),
+        (0x2, _, _, _) => format!("CALL {:#05X}", nnn),
+        (0x3, _, _, _) => format!("SE V{:X}, {:#04X}", x, nn),
+        (0x4, _, _, _) => format!("SNE V{:X}, {:#04X}", x, nn),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x5, _, _, 0x2) if xo_chip => format!("LD [I], V{:X}-V{:X}", x, y),
+        (0x5, _, _, 0x3) if xo_chip => format!("LD V{:X}-V{:X}, [I]", x, y),
+        (0x6, _, _, _) => format!("LD V{:X}, {:#04X}", x, nn),
+        (0x7, _, _, _) => format!("ADD V{:X}, {:#04X}", x, nn),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, {:#05X}", nnn),
+        (0xB, _, _, _) => format!("JP V{:X}, {:#05X}", jump_register, nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, {:#04X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0x0, 0x1) if xo_chip => format!("PLANE {}", x),
+        (0xF, 0x0, 0x0, 0x2) if xo_chip => "AUDIO".to_string(),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x0) => format!("LD HF, V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x3, 0xA) if xo_chip => format!("PITCH V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 0x7, 0x5) => format!("LD R, V{:X}", x),
+        (0xF, _, 0x8, 0x5) => format!("LD V{:X}, R", x),
+        _ => format!("DW {:#06X}", opcode.hex),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+
+    fn config(platform: Platform) -> Config {
+        Config {
+            platform,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mnemonics() {
+        let rom = [0x61, 0x23, 0xD0, 0x15, 0x00, 0xE0, 0xF1, 0x0A, 0xFF, 0xFF];
+        let listing = disassemble(&rom, 0x200, &config(Platform::Chip8));
+
+        let mnemonics: Vec<&str> = listing.iter().map(|i| i.mnemonic.as_str()).collect();
+        assert_eq!(
+            mnemonics,
+            [
+                "LD V1, 0x23",
+                "DRW V0, V1, 5",
+                "CLS",
+                "LD V1, K",
+                "DW 0xFFFF"
+            ]
+        );
+        assert_eq!(listing[1].address, 0x202);
+        assert_eq!(listing[1].to_string(), "0x0202  D015      DRW V0, V1, 5");
+    }
+
+    #[test]
+    fn platform_variants() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0x51, 0x22, 0x00, 0xFF, 0x12];
+
+        let listing = disassemble(&rom, 0x200, &config(Platform::XoChip));
+        let mnemonics: Vec<&str> = listing.iter().map(|i| i.mnemonic.as_str()).collect();
+        assert_eq!(
+            mnemonics,
+            ["LD I, 0x1234", "LD [I], V1-V2", "HIGH", "DB 0x12"]
+        );
+        assert_eq!(listing[1].address, 0x204);
+
+        let listing = disassemble(&rom, 0x200, &config(Platform::Chip8));
+        assert_eq!(listing[0].mnemonic, "DW 0xF000");
+        assert_eq!(listing[1].mnemonic, "JP 0x234");
+        assert_eq!(listing[2].mnemonic, "DW 0x5122");
+    }
+
+    #[test]
+    fn chip8_memory() {
+        let mut c8 = Chip8::default();
+        c8.load_rom(&[0x12, 0x00]).unwrap();
+
+        let listing = c8.disassemble(0x200..0x202);
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].mnemonic, "JP 0x200");
+
+        let listing = c8.disassemble(0xFFE..0x2000);
+        assert_eq!(listing.len(), 1);
+    }
+
+    #[test]
+    fn jump_quirk() {
+        let rom = [0xB3, 0x10];
+        let listing = disassemble(&rom, 0x200, &Config::default());
+        assert_eq!(listing[0].mnemonic, "JP V0, 0x310");
+
+        let config = Config {
+            quirks: Quirks::SCHIP_1_1,
+            ..Default::default()
+        };
+        let listing = disassemble(&rom, 0x200, &config);
+        assert_eq!(listing[0].mnemonic, "JP V3, 0x310");
+    }
+}
@@ -18,4 +18,52 @@ pub enum ChipError {
     /// Thrown by the CPU when attempting to execute an unknown opcode
     #[error("The opcode {:#06x} is not implemented", .opcode)]
     OpcodeNotImplemented { opcode: u16 },
+
+    /// Thrown by the CPU when checking a key outside of the 0x0 - 0xF keypad
+    #[error("The key {0:#04x} is not on the keypad")]
+    InvalidKey(u8),
+
+    /// Thrown when restoring a save state that is corrupt or was made for a different machine
+    #[error("Invalid save state: {0}")]
+    InvalidSaveState(String),
+
+    /// Thrown when parsing a movie file that is corrupt or from an unsupported version
+    #[error("Invalid movie: {0}")]
+    InvalidMovie(String),
+
+    /// Thrown when replaying a movie with a different ROM than the one it was recorded with
+    #[error("The ROM does not match the one the movie was recorded with")]
+    MovieRomMismatch,
+
+    /// Thrown when reading a binary trace log that is corrupt or from an unsupported version
+    #[error("Invalid trace: {0}")]
+    InvalidTrace(String),
+
+    /// Thrown by the assembler when the source code of a program is invalid
+    #[error("Line {line}, column {column}: {message}")]
+    InvalidSource {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    /// Thrown when the buffer provided to render the screen can't hold all of its pixels
+    #[error("The buffer holds {provided} values but {needed} are needed")]
+    BufferTooSmall { needed: usize, provided: usize },
+
+    /// Thrown when creating a palette with an amount of colors other than 2, 4 or 16
+    #[error("A palette needs 2, 4 or 16 colors, got {0}")]
+    InvalidPalette(usize),
+
+    /// Thrown when parsing keymap overrides that are invalid
+    #[error("Invalid keymap: {0}")]
+    InvalidKeymap(String),
+
+    /// Thrown when reading a ROM database that is not in the format of the CHIP-8 database
+    #[error("Invalid ROM database: {0}")]
+    InvalidDatabase(String),
+
+    /// Thrown when reading an Octo cartridge that is not a valid GIF or holds no program
+    #[error("Invalid cartridge: {0}")]
+    InvalidCartridge(String),
 }
@@ -0,0 +1,509 @@
+use std::collections::HashMap;
+
+use crate::{Chip8, Palette, Screen};
+
+// Every frame uses the 16 colors of the palette, 4 bits per pixel
+const MIN_CODE_SIZE: u8 = 4;
+const MAX_CODE_SIZE: u8 = 12;
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+
+// Decoded images can't hold more pixels than this in total, so a corrupt or
+// hostile header can't make decoding allocate gigabytes
+const MAX_PIXELS: usize = 1 << 24;
+
+// The timers run at 60 Hz while GIF delays are in hundredths of a second
+const FRAME_RATE: usize = 60;
+
+// A screen captured for one or more frames in a row
+struct Frame {
+    width: usize,
+    pixels: Vec<u8>,
+    frames: usize,
+}
+
+impl Frame {
+    fn height(&self) -> usize {
+        self.pixels.len() / self.width
+    }
+
+    // The pixels with every one of them repeated as a square of scale by scale
+    fn scaled(&self, scale: usize) -> Vec<u8> {
+        let mut scaled = Vec::with_capacity(self.pixels.len() * scale * scale);
+        for row in self.pixels.chunks(self.width) {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel, scale))
+                .collect();
+            for _ in 0..scale {
+                scaled.extend_from_slice(&line);
+            }
+        }
+
+        scaled
+    }
+}
+
+/// Records the screen of every frame and encodes them into an animated GIF
+/// looping forever.
+///
+/// Set it in [`Chip8::gif`] and the screen is captured at the end of every frame,
+/// or call [`GifRecorder::capture`] directly. Frames that don't change the screen
+/// are merged into the previous one. The recording is sized for the highest
+/// resolution it holds, lower resolution frames are scaled up to fill it.
+pub struct GifRecorder {
+    palette: Palette,
+    scale: usize,
+    frames: Vec<Frame>,
+}
+
+impl GifRecorder {
+    /// Create a recorder drawing every pixel as a square of `scale` by `scale` in the
+    /// colors of the palette.
+    pub fn new(palette: Palette, scale: usize) -> Self {
+        GifRecorder {
+            palette,
+            scale: scale.max(1),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Add the screen as the next 60 Hz frame.
+    pub fn capture(&mut self, screen: &Screen) {
+        if let Some(last) = self.frames.last_mut() {
+            if last.width == screen.width && last.pixels == screen.pixels() {
+                last.frames += 1;
+                return;
+            }
+        }
+
+        self.frames.push(Frame {
+            width: screen.width,
+            pixels: screen.pixels().to_vec(),
+            frames: 1,
+        });
+    }
+
+    /// How many 60 Hz frames were captured.
+    pub fn frame_count(&self) -> usize {
+        self.frames.iter().map(|frame| frame.frames).sum()
+    }
+
+    /// Encode the captured frames. Every frame is shown for 1/60 of a second, rounded
+    /// to the hundredths of a second of the GIF format so the animation keeps the
+    /// pace of the machine. Viewers slow down delays under 2 hundredths, so frames
+    /// that change faster are merged, showing the latest screen.
+    pub fn to_gif(&self) -> Vec<u8> {
+        let max_width = self.frames.iter().map(|f| f.width).max().unwrap_or(0);
+        let max_height = self.frames.iter().map(|f| f.height()).max().unwrap_or(0);
+        let (width, height) = (max_width * self.scale, max_height * self.scale);
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&(width as u16).to_le_bytes());
+        gif.extend_from_slice(&(height as u16).to_le_bytes());
+        // Global color table of 16 colors
+        gif.extend_from_slice(&[0xF3, 0, 0]);
+        for index in 0..16 {
+            gif.extend_from_slice(&self.palette.color(index).to_be_bytes()[1..]);
+        }
+        // Loop forever
+        gif.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        let lengths: Vec<usize> = self.frames.iter().map(|frame| frame.frames).collect();
+        for (index, delay) in merged_delays(&lengths) {
+            let frame = &self.frames[index];
+
+            // Graphic control extension holding the delay
+            gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x04]);
+            gif.extend_from_slice(&(delay as u16).to_le_bytes());
+            gif.extend_from_slice(&[0x00, 0x00]);
+
+            // Image descriptor covering the whole image, without a local color table
+            gif.extend_from_slice(&[0x2C, 0, 0, 0, 0]);
+            gif.extend_from_slice(&(width as u16).to_le_bytes());
+            gif.extend_from_slice(&(height as u16).to_le_bytes());
+            gif.push(0);
+
+            let data = lzw_encode(&frame.scaled(self.scale * max_width / frame.width));
+
+            gif.push(MIN_CODE_SIZE);
+            for block in data.chunks(255) {
+                gif.push(block.len() as u8);
+                gif.extend_from_slice(block);
+            }
+            gif.push(0);
+        }
+        gif.push(0x3B);
+
+        gif
+    }
+}
+
+impl Chip8 {
+    // Captures the screen of the frame that just ended
+    pub(crate) fn capture_gif_frame(&mut self) {
+        if let Some(gif) = &mut self.gif {
+            gif.capture(&self.screen);
+        }
+    }
+}
+
+// The time at which the frame starts, rounded to hundredths of a second
+fn centiseconds(frame: usize) -> usize {
+    (frame * 100 + FRAME_RATE / 2) / FRAME_RATE
+}
+
+// Viewers show frames with a delay of 0 or 1 hundredth of a second for much longer
+const MIN_DELAY: usize = 2;
+
+// The frames to show, given how many 60 Hz frames each captured frame lasts, as
+// the index of the captured frame and its delay in hundredths of a second. Frames
+// too short to show on their own are merged with the ones after them into the
+// latest of them, keeping the total time.
+fn merged_delays(lengths: &[usize]) -> Vec<(usize, usize)> {
+    let mut delays = Vec::new();
+    let mut start = 0;
+    let mut elapsed = 0;
+    for (index, length) in lengths.iter().enumerate() {
+        elapsed += length;
+        let delay = centiseconds(elapsed) - centiseconds(start);
+        if delay >= MIN_DELAY || index == lengths.len() - 1 {
+            delays.push((index, delay));
+            start = elapsed;
+        }
+    }
+
+    delays
+}
+
+// Variable length LZW codes as used by GIF, packed starting from the least
+// significant bit
+fn lzw_encode(indexes: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut bits = 0u32;
+    let mut len = 0;
+    let mut emit = |code: u16, size: u8| {
+        bits |= (code as u32) << len;
+        len += size;
+        while len >= 8 {
+            data.push(bits as u8);
+            bits >>= 8;
+            len -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = END_CODE + 1;
+    let mut code_size = MIN_CODE_SIZE + 1;
+    emit(CLEAR_CODE, code_size);
+
+    let mut prefix: Option<u16> = None;
+    for &index in indexes {
+        let Some(current) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = table.get(&(current, index)) {
+            prefix = Some(code);
+            continue;
+        }
+
+        emit(current, code_size);
+        match next_code < 1 << MAX_CODE_SIZE {
+            true => {
+                table.insert((current, index), next_code);
+                next_code += 1;
+                if next_code > 1 << code_size && code_size < MAX_CODE_SIZE {
+                    code_size += 1;
+                }
+            }
+            false => {
+                // The table is full, start over
+                emit(CLEAR_CODE, code_size);
+                table.clear();
+                next_code = END_CODE + 1;
+                code_size = MIN_CODE_SIZE + 1;
+            }
+        }
+        prefix = Some(index as u16);
+    }
+    if let Some(current) = prefix {
+        emit(current, code_size);
+    }
+    emit(END_CODE, code_size);
+    if len > 0 {
+        data.push(bits as u8);
+    }
+
+    data
+}
+
+// The images of a GIF file in order, as the color index of every pixel row by row.
+// Colors and extensions are skipped.
+pub(crate) fn decode_gif(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+        return Err("not a GIF file".to_string());
+    }
+    let byte = |pos: usize| {
+        data.get(pos)
+            .copied()
+            .ok_or_else(|| "unexpected end of the file".to_string())
+    };
+    let word = |pos: usize| Ok::<_, String>(u16::from_le_bytes([byte(pos)?, byte(pos + 1)?]));
+
+    let mut pos = 13;
+    let flags = byte(10)?;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 0x07) + 1);
+    }
+
+    let mut images = Vec::new();
+    let mut total_pixels = 0;
+    loop {
+        match byte(pos)? {
+            // Extension
+            0x21 => {
+                pos += 2;
+                sub_blocks(data, &mut pos)?;
+            }
+            // Image descriptor
+            0x2C => {
+                let width = word(pos + 5)? as usize;
+                let height = word(pos + 7)? as usize;
+                let flags = byte(pos + 9)?;
+                pos += 10;
+                if flags & 0x80 != 0 {
+                    pos += 3 << ((flags & 0x07) + 1);
+                }
+                let min_code_size = byte(pos)?;
+                pos += 1;
+
+                total_pixels += width * height;
+                if total_pixels > MAX_PIXELS {
+                    return Err(format!("the images hold more than {} pixels", MAX_PIXELS));
+                }
+                let data = sub_blocks(data, &mut pos)?;
+                let mut indexes = lzw_decode(&data, min_code_size, width * height)?;
+                indexes.resize(width * height, 0);
+                if flags & 0x40 != 0 {
+                    indexes = deinterlace(&indexes, width, height);
+                }
+                images.push(indexes);
+            }
+            // Trailer
+            0x3B => return Ok(images),
+            block => return Err(format!("unknown block {:#04x}", block)),
+        }
+    }
+}
+
+// The data of the sub-blocks starting at the position, which is moved past them
+fn sub_blocks(data: &[u8], pos: &mut usize) -> Result<Vec<u8>, String> {
+    let mut blocks = Vec::new();
+    loop {
+        let len = *data.get(*pos).ok_or("unexpected end of the file")? as usize;
+        *pos += 1;
+        if len == 0 {
+            return Ok(blocks);
+        }
+        let block = data
+            .get(*pos..*pos + len)
+            .ok_or("unexpected end of the file")?;
+        blocks.extend_from_slice(block);
+        *pos += len;
+    }
+}
+
+// Decoding stops once the image is full, the rest of the data is ignored
+fn lzw_decode(data: &[u8], min_code_size: u8, max_len: usize) -> Result<Vec<u8>, String> {
+    if !(2..=8).contains(&min_code_size) {
+        return Err(format!("invalid LZW code size {}", min_code_size));
+    }
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+
+    // Every code is the code of its prefix followed by a last index
+    let table_size = 1 << MAX_CODE_SIZE;
+    let mut prefixes = vec![0u16; table_size];
+    let mut suffixes: Vec<u8> = (0..table_size).map(|code| code as u8).collect();
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+
+    let mut indexes = Vec::new();
+    let mut sequence = Vec::new();
+    let mut previous: Option<u16> = None;
+    let mut bits = 0u32;
+    let mut len = 0;
+    let mut bytes = data.iter();
+    loop {
+        while len < code_size {
+            let Some(&byte) = bytes.next() else {
+                // Some encoders leave out the end code
+                return Ok(indexes);
+            };
+            bits |= (byte as u32) << len;
+            len += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as u16;
+        bits >>= code_size;
+        len -= code_size;
+
+        if code == clear_code {
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end_code {
+            return Ok(indexes);
+        }
+
+        // The code right after the last one of the table is the previous sequence
+        // followed by its own first index
+        let known = code < next_code && (code < clear_code || code > end_code);
+        let start = match (known, previous) {
+            (true, _) => code,
+            (false, Some(previous)) if code == next_code => previous,
+            _ => return Err(format!("invalid LZW code {}", code)),
+        };
+        sequence.clear();
+        let mut current = start;
+        while current > end_code {
+            sequence.push(suffixes[current as usize]);
+            current = prefixes[current as usize];
+        }
+        sequence.push(suffixes[current as usize]);
+        sequence.reverse();
+        let first = sequence[0];
+        if !known {
+            sequence.push(first);
+        }
+        indexes.extend_from_slice(&sequence);
+        if indexes.len() >= max_len {
+            indexes.truncate(max_len);
+            return Ok(indexes);
+        }
+
+        if let Some(previous) = previous {
+            if (next_code as usize) < table_size {
+                prefixes[next_code as usize] = previous;
+                suffixes[next_code as usize] = first;
+                next_code += 1;
+                if next_code == 1 << code_size && code_size < MAX_CODE_SIZE {
+                    code_size += 1;
+                }
+            }
+        }
+        previous = Some(code);
+    }
+}
+
+// Interlaced images store every 8th row from 0, every 8th row from 4, every 4th
+// row from 2 and then every other row from 1
+fn deinterlace(indexes: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let rows = (0..height)
+        .step_by(8)
+        .chain((4..height).step_by(8))
+        .chain((2..height).step_by(4))
+        .chain((1..height).step_by(2));
+    let mut deinterlaced = vec![0; indexes.len()];
+    for (row, y) in indexes.chunks(width.max(1)).zip(rows) {
+        deinterlaced[y * width..(y + 1) * width].copy_from_slice(row);
+    }
+
+    deinterlaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChipRng, XorShiftRng};
+
+    #[test]
+    fn frame_delays() {
+        // 3 frames of 60 Hz take 5 hundredths of a second
+        let delays: Vec<usize> = (0..3)
+            .map(|f| centiseconds(f + 1) - centiseconds(f))
+            .collect();
+        assert_eq!(delays, [2, 1, 2]);
+        assert_eq!(centiseconds(60), 100);
+
+        // A screen changing every frame is shown every 2 or 3 hundredths
+        let merged = merged_delays(&[1; 6]);
+        assert_eq!(merged, [(0, 2), (2, 3), (3, 2), (5, 3)]);
+        let lengths = [1, 1, 4, 1, 2, 1, 1, 1, 30];
+        let merged = merged_delays(&lengths);
+        assert!(merged.iter().all(|&(_, delay)| delay >= MIN_DELAY));
+        let total: usize = merged.iter().map(|&(_, delay)| delay).sum();
+        assert_eq!(total, centiseconds(lengths.iter().sum()));
+        // The last screen is always shown
+        assert_eq!(merged.last().unwrap().0, lengths.len() - 1);
+    }
+
+    #[test]
+    fn record() {
+        let mut c8 = Chip8::default();
+        // 0x200: LD F, V1 ; 0x202: DRW V0, V0, 5 ; 0x204: ADD V0, 1 ; 0x206: JP 0x202
+        c8.load_rom(&[0xF1, 0x29, 0xD0, 0x05, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+        c8.config.tick_rate = 3;
+        c8.gif = Some(GifRecorder::new(Palette::default(), 2));
+
+        for _ in 0..4 {
+            c8.tick().unwrap();
+        }
+        // The screen stays the same once the program halts
+        c8.cpu.halted = true;
+        c8.tick().unwrap();
+
+        let gif = c8.gif.take().unwrap();
+        assert_eq!(gif.frame_count(), 5);
+        assert_eq!(gif.frames.len(), 4);
+        assert_eq!(gif.frames[3].frames, 2);
+
+        let data = gif.to_gif();
+        assert_eq!(data[..6], *b"GIF89a");
+        assert_eq!(data[6..10], [128, 0, 64, 0]);
+        assert_eq!(data.last(), Some(&0x3B));
+    }
+
+    #[test]
+    fn decode() {
+        let mut c8 = Chip8::default();
+        c8.screen.set_pixel(3, 2, 1);
+        let mut recorder = GifRecorder::new(Palette::default(), 1);
+        recorder.capture(&c8.screen);
+        c8.screen.set_hires(true);
+        c8.screen.set_pixel(100, 60, 1);
+        recorder.capture(&c8.screen);
+
+        let images = decode_gif(&recorder.to_gif()).unwrap();
+        assert_eq!(images.len(), 2);
+        // The low resolution frame is scaled up to fill the image
+        assert_eq!(images[0], recorder.frames[0].scaled(2));
+        assert_eq!(images[1], c8.screen.pixels());
+
+        assert!(decode_gif(b"PNG").is_err());
+
+        // A 65535x65535 image with no data
+        let mut huge = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+        huge.extend_from_slice(b"\x2C\x00\x00\x00\x00\xFF\xFF\xFF\xFF\x00\x02\x00\x3B");
+        let e = decode_gif(&huge).unwrap_err();
+        assert!(e.contains("pixels"), "{}", e);
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // Random data fills the table many times over
+        let mut rng = XorShiftRng::new(1);
+        let random: Vec<u8> = (0..50_000).map(|_| rng.next_u8() & 0x0F).collect();
+        let repeated: Vec<u8> = (0..50_000).map(|i| (i / 700 % 16) as u8).collect();
+        for indexes in [random, repeated, vec![7], vec![]] {
+            let encoded = lzw_encode(&indexes);
+            let decoded = lzw_decode(&encoded, MIN_CODE_SIZE, indexes.len()).unwrap();
+            assert_eq!(decoded, indexes);
+            let decoded = lzw_decode(&encoded, MIN_CODE_SIZE, 100).unwrap();
+            assert_eq!(decoded, indexes[..indexes.len().min(100)]);
+        }
+    }
+}
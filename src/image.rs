@@ -0,0 +1,410 @@
+use crate::{Palette, Screen};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Deflate looks back at most this far for a repeated sequence
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// How many earlier positions with the same 3 bytes are tried for each match
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 15;
+
+// Base lengths and extra bits of the deflate length codes 257 to 285
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Base distances and extra bits of the deflate distance codes
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+impl Screen {
+    /// Encode the screen as a binary PPM image, with every pixel drawn as a square
+    /// of `scale` by `scale` in the colors of the palette.
+    pub fn to_ppm(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        let (width, height) = self.scaled_size(scale);
+        let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for &index in &self.scaled_indexes(scale) {
+            ppm.extend_from_slice(&palette.color(index).to_be_bytes()[1..]);
+        }
+
+        ppm
+    }
+
+    /// Encode the screen as a PNG image, with every pixel drawn as a square of
+    /// `scale` by `scale` in the colors of the palette.
+    pub fn to_png(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        let (width, height) = self.scaled_size(scale);
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        // 8 bits per pixel indexing the palette, no interlacing
+        header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+        let mut colors = Vec::with_capacity(16 * 3);
+        for index in 0..16 {
+            colors.extend_from_slice(&palette.color(index).to_be_bytes()[1..]);
+        }
+
+        // Every line starts with the filter type, none here
+        let mut lines = Vec::with_capacity((width + 1) * height);
+        for line in self.scaled_indexes(scale).chunks(width.max(1)) {
+            lines.push(0);
+            lines.extend_from_slice(line);
+        }
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"PLTE", &colors);
+        write_chunk(&mut png, b"IDAT", &zlib(&lines));
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    // The color index of every pixel once scaled, row by row
+    pub(crate) fn scaled_indexes(&self, scale: usize) -> Vec<u8> {
+        let (width, height) = self.scaled_size(scale);
+        let mut indexes = vec![0; width * height];
+        self.render(
+            &std::array::from_fn(|index| [index as u8]),
+            scale,
+            &mut indexes,
+        );
+
+        indexes
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB88320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    b << 16 | a
+}
+
+// Writes bits starting from the least significant bit of every byte
+struct BitWriter {
+    data: Vec<u8>,
+    bits: u32,
+    len: u8,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, len: u8) {
+        self.bits |= value << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.data.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    // Huffman codes are stored starting from their most significant bit
+    fn code(&mut self, code: u32, len: u8) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.data.push(self.bits as u8);
+        }
+
+        self.data
+    }
+}
+
+// A zlib stream holding a single deflate block compressed with the fixed Huffman codes
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        data: vec![0x78, 0x01],
+        bits: 0,
+        len: 0,
+    };
+    // Final block with fixed codes
+    w.bits(0b011, 3);
+
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut previous = vec![usize::MAX; data.len()];
+    let hash = |pos: usize| {
+        ((data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize)
+            % HASH_SIZE
+    };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(pos)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+                    break;
+                }
+                let len = (0..max_len)
+                    .take_while(|&i| data[candidate + i] == data[pos + i])
+                    .count();
+                if len > best.0 {
+                    best = (len, pos - candidate);
+                }
+                candidate = previous[candidate];
+            }
+        }
+
+        let step = match best {
+            (len, distance) if len >= MIN_MATCH => {
+                write_match(&mut w, len, distance);
+                len
+            }
+            _ => {
+                write_symbol(&mut w, data[pos] as u16);
+                1
+            }
+        };
+        for p in (pos..pos + step).take_while(|p| p + MIN_MATCH <= data.len()) {
+            let h = hash(p);
+            previous[p] = head[h];
+            head[h] = p;
+        }
+        pos += step;
+    }
+    write_symbol(&mut w, 256);
+
+    let mut zlib = w.finish();
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+
+    zlib
+}
+
+// Literals, the end of the block and the length codes in the fixed Huffman codes
+fn write_symbol(w: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => w.code(0x30 + symbol, 8),
+        144..=255 => w.code(0x190 + symbol - 144, 9),
+        256..=279 => w.code(symbol - 256, 7),
+        _ => w.code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(w: &mut BitWriter, len: usize, distance: usize) {
+    let code = LENGTH_BASES
+        .iter()
+        .rposition(|&base| base as usize <= len)
+        .unwrap();
+    write_symbol(w, 257 + code as u16);
+    w.bits(
+        (len - LENGTH_BASES[code] as usize) as u32,
+        LENGTH_EXTRA_BITS[code],
+    );
+
+    let code = DISTANCE_BASES
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    w.code(code as u32, 5);
+    w.bits(
+        (distance - DISTANCE_BASES[code] as usize) as u32,
+        DISTANCE_EXTRA_BITS[code],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChipRng, XorShiftRng};
+
+    // Reads bits starting from the least significant bit of every byte
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, len: u8) -> u32 {
+            (0..len).fold(0, |value, i| {
+                let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+                self.pos += 1;
+                value | (bit as u32) << i
+            })
+        }
+
+        // Huffman codes are read starting from their most significant bit
+        fn code(&mut self, len: u8) -> u32 {
+            (0..len).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        fn fixed_symbol(&mut self) -> u16 {
+            let mut code = self.code(7);
+            if code <= 0x17 {
+                return 256 + code as u16;
+            }
+            code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xBF => (code - 0x30) as u16,
+                0xC0..=0xC7 => (280 + code - 0xC0) as u16,
+                _ => (144 + (code << 1 | self.bits(1)) - 0x190) as u16,
+            }
+        }
+    }
+
+    // Decodes the zlib streams written by the encoder, made of fixed Huffman blocks
+    fn inflate(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(zlib[0] & 0x0F, 8, "not deflate");
+        assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+        let mut r = BitReader {
+            data: &zlib[2..zlib.len() - 4],
+            pos: 0,
+        };
+
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            let last = r.bits(1) == 1;
+            assert_eq!(r.bits(2), 1, "not a fixed Huffman block");
+            loop {
+                let code = match r.fixed_symbol() {
+                    256 => break,
+                    literal @ 0..=255 => {
+                        data.push(literal as u8);
+                        continue;
+                    }
+                    symbol => (symbol - 257) as usize,
+                };
+                let len = LENGTH_BASES[code] as u32 + r.bits(LENGTH_EXTRA_BITS[code]);
+                let code = r.code(5) as usize;
+                let distance = DISTANCE_BASES[code] as u32 + r.bits(DISTANCE_EXTRA_BITS[code]);
+                for _ in 0..len {
+                    data.push(data[data.len() - distance as usize]);
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&data).to_be_bytes());
+
+        data
+    }
+
+    // The data of the chunks of the kind, in order
+    fn chunks<'a>(png: &'a [u8], kind: &[u8; 4]) -> Vec<&'a [u8]> {
+        let mut found = Vec::new();
+        let mut pos = PNG_SIGNATURE.len();
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            if png[pos + 4..pos + 8] == *kind {
+                found.push(&png[pos + 8..pos + 8 + len]);
+            }
+            pos += 12 + len;
+        }
+
+        found
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn ppm() {
+        let mut screen = Screen::default();
+        screen.set_pixel(1, 0, 1);
+        let palette = Palette::new(0x102030, 0xFFEEDD);
+
+        let ppm = screen.to_ppm(&palette, 2);
+        let header = b"P6\n128 64\n255\n";
+        assert_eq!(ppm[..header.len()], *header);
+        assert_eq!(ppm.len(), header.len() + 128 * 64 * 3);
+        let pixels = &ppm[header.len()..];
+        assert_eq!(
+            pixels[..9],
+            [0x10, 0x20, 0x30, 0x10, 0x20, 0x30, 0xFF, 0xEE, 0xDD]
+        );
+        assert_eq!(pixels[128 * 3 + 6..128 * 3 + 9], [0xFF, 0xEE, 0xDD]);
+    }
+
+    #[test]
+    fn png() {
+        let mut screen = Screen::default();
+        screen.set_pixel(5, 5, 1);
+        let png = screen.to_png(&Palette::default(), 4);
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        // The header chunk holds the scaled size
+        assert_eq!(png[12..16], *b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 1, 0, 0, 0, 0, 128]);
+        assert_eq!(
+            png[png.len() - 12..],
+            [0, 0, 0, 0, 73, 69, 78, 68, 0xAE, 0x42, 0x60, 0x82]
+        );
+        // The repeated pixels compress well
+        assert!(png.len() < 1024);
+
+        // Every line starts with the filter type and the pixel is a 4x4 square
+        let lines = inflate(chunks(&png, b"IDAT")[0]);
+        assert_eq!(lines.len(), 128 * 257);
+        for (y, line) in lines.chunks(257).enumerate() {
+            assert_eq!(line[0], 0);
+            let lit: Vec<usize> = (0..256).filter(|&x| line[1 + x] == 1).collect();
+            match (20..24).contains(&y) {
+                true => assert_eq!(lit, [20, 21, 22, 23]),
+                false => assert!(lit.is_empty(), "line {}", y),
+            }
+        }
+    }
+
+    #[test]
+    fn deflate() {
+        // Long runs, short repeats far apart and data that doesn't repeat
+        let mut rng = XorShiftRng::new(3);
+        let mut data = vec![7; 1000];
+        data.extend((0..3000).map(|i| (i % 37) as u8));
+        data.extend((0..2000).map(|_| rng.next_u8()));
+        data.extend_from_within(100..);
+
+        for data in [data, Vec::new(), vec![1, 2]] {
+            assert_eq!(inflate(&zlib(&data)), data);
+        }
+    }
+}
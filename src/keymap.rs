@@ -0,0 +1,292 @@
+use crate::ChipError;
+
+/// The preset layouts of a [Keymap].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// The keypad of the COSMAC VIP on the left side of a QWERTY keyboard:
+    ///
+    /// ```text
+    /// 1 2 3 4        1 2 3 C
+    /// Q W E R        4 5 6 D
+    /// A S D F   ->   7 8 9 E
+    /// Z X C V        A 0 B F
+    /// ```
+    #[default]
+    CosmacVip,
+    /// The keypad the way Super-Chip placed it on the HP48 calculators, on the
+    /// numeric keypad:
+    ///
+    /// ```text
+    /// 7 8 9 /        1 2 3 C
+    /// 4 5 6 *        4 5 6 D
+    /// 1 2 3 -   ->   7 8 9 E
+    /// 0 . ⏎ +        A 0 B F
+    /// ```
+    Hp48,
+    /// The directions on the D-pad and the arrow keys as 2, 4, 6 and 8 like most
+    /// games use them, with 5 on the south face button and Space. The other
+    /// buttons cover the remaining keys games commonly use.
+    Gamepad,
+}
+
+impl Layout {
+    /// The layout with the name used by the override files: `vip`, `hp48` or `gamepad`.
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "vip" => Some(Layout::CosmacVip),
+            "hp48" => Some(Layout::Hp48),
+            "gamepad" => Some(Layout::Gamepad),
+            _ => None,
+        }
+    }
+
+    fn bindings(self) -> &'static [(&'static str, u8)] {
+        match self {
+            Layout::CosmacVip => &[
+                ("Digit1", 0x1),
+                ("Digit2", 0x2),
+                ("Digit3", 0x3),
+                ("Digit4", 0xC),
+                ("KeyQ", 0x4),
+                ("KeyW", 0x5),
+                ("KeyE", 0x6),
+                ("KeyR", 0xD),
+                ("KeyA", 0x7),
+                ("KeyS", 0x8),
+                ("KeyD", 0x9),
+                ("KeyF", 0xE),
+                ("KeyZ", 0xA),
+                ("KeyX", 0x0),
+                ("KeyC", 0xB),
+                ("KeyV", 0xF),
+            ],
+            Layout::Hp48 => &[
+                ("Numpad7", 0x1),
+                ("Numpad8", 0x2),
+                ("Numpad9", 0x3),
+                ("NumpadDivide", 0xC),
+                ("Numpad4", 0x4),
+                ("Numpad5", 0x5),
+                ("Numpad6", 0x6),
+                ("NumpadMultiply", 0xD),
+                ("Numpad1", 0x7),
+                ("Numpad2", 0x8),
+                ("Numpad3", 0x9),
+                ("NumpadSubtract", 0xE),
+                ("Numpad0", 0xA),
+                ("NumpadDecimal", 0x0),
+                ("NumpadEnter", 0xB),
+                ("NumpadAdd", 0xF),
+            ],
+            Layout::Gamepad => &[
+                ("DPadUp", 0x2),
+                ("DPadLeft", 0x4),
+                ("DPadRight", 0x6),
+                ("DPadDown", 0x8),
+                ("ArrowUp", 0x2),
+                ("ArrowLeft", 0x4),
+                ("ArrowRight", 0x6),
+                ("ArrowDown", 0x8),
+                ("South", 0x5),
+                ("Space", 0x5),
+                ("East", 0x6),
+                ("West", 0x4),
+                ("North", 0x0),
+                ("LeftShoulder", 0x1),
+                ("RightShoulder", 0xC),
+                ("Select", 0xE),
+                ("Start", 0xF),
+            ],
+        }
+    }
+}
+
+/// Turns the keys of the host into the keys of the keypad.
+///
+/// Host keys are identified by name: keyboard keys by the `code` of web keyboard
+/// events (`KeyQ`, `Digit1`, `Numpad7`, `ArrowUp`, `Space`...) which most windowing
+/// libraries share, and gamepad buttons by `DPadUp`, `South`, `Start`... Names are
+/// compared ignoring case, and several host keys can press the same key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: Vec<(String, u8)>,
+}
+
+impl Keymap {
+    /// A keymap with the bindings of the layout.
+    pub fn new(layout: Layout) -> Self {
+        Keymap {
+            bindings: layout
+                .bindings()
+                .iter()
+                .map(|&(host, key)| (host.to_string(), key))
+                .collect(),
+        }
+    }
+
+    /// A keymap without any bindings.
+    pub fn empty() -> Self {
+        Keymap {
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Make the host key press the key of the keypad (0x0 - 0xF), replacing what
+    /// it was bound to.
+    pub fn bind(&mut self, host: &str, key: u8) -> Result<(), ChipError> {
+        if key > 0xF {
+            return Err(ChipError::InvalidKey(key));
+        }
+        self.unbind(host);
+        self.bindings.push((host.to_string(), key));
+
+        Ok(())
+    }
+
+    /// Remove the binding of the host key.
+    pub fn unbind(&mut self, host: &str) {
+        self.bindings
+            .retain(|(bound, _)| !bound.eq_ignore_ascii_case(host));
+    }
+
+    /// The key of the keypad the host key presses, for frontends that pass on
+    /// key events with [`Chip8::key_down`](crate::Chip8::key_down).
+    pub fn key(&self, host: &str) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| bound.eq_ignore_ascii_case(host))
+            .map(|&(_, key)| key)
+    }
+
+    /// The state of the keypad while the host keys are held, ready for
+    /// [`Chip8::set_input`](crate::Chip8::set_input). Unbound host keys are ignored.
+    pub fn keypad<'a>(&self, held: impl IntoIterator<Item = &'a str>) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for key in held.into_iter().filter_map(|host| self.key(host)) {
+            keypad[key as usize] = true;
+        }
+
+        keypad
+    }
+
+    /// Apply the overrides of a ROM, one per line:
+    ///
+    /// ```text
+    /// # Comments start with a hash
+    /// layout gamepad   # Start over from a preset: vip, hp48 or gamepad
+    /// KeyW = 5         # Bind a host key to a key of the keypad, in hexadecimal
+    /// Space = -        # Unbind a host key
+    /// ```
+    ///
+    /// The keymap is left unchanged if any line is invalid.
+    pub fn apply_overrides(&mut self, text: &str) -> Result<(), ChipError> {
+        let mut keymap = self.clone();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |message: String| {
+                ChipError::InvalidKeymap(format!("line {}: {}", number + 1, message))
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix("layout ") {
+                let layout = Layout::from_name(name.trim())
+                    .ok_or_else(|| error(format!("unknown layout '{}'", name.trim())))?;
+                keymap = Keymap::new(layout);
+                continue;
+            }
+
+            let (host, key) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `HOST = KEY`, got '{}'", line)))?;
+            let (host, key) = (host.trim(), key.trim());
+            if host.is_empty() || host.contains(char::is_whitespace) {
+                return Err(error(format!("invalid host key '{}'", host)));
+            }
+            match key {
+                "-" => keymap.unbind(host),
+                _ => {
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|&key| key <= 0xF)
+                        .ok_or_else(|| error(format!("invalid keypad key '{}'", key)))?;
+                    keymap.bind(host, key)?;
+                }
+            }
+        }
+        *self = keymap;
+
+        Ok(())
+    }
+}
+
+/// The COSMAC VIP layout.
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::new(Layout::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.key("KeyV"), Some(0xF));
+        assert_eq!(keymap.key("keyx"), Some(0x0));
+        assert_eq!(keymap.key("ArrowUp"), None);
+
+        let keypad = keymap.keypad(["Digit4", "KeyS", "Escape"]);
+        let pressed: Vec<usize> = (0..16).filter(|&key| keypad[key]).collect();
+        assert_eq!(pressed, [0x8, 0xC]);
+
+        let keymap = Keymap::new(Layout::Hp48);
+        assert_eq!(keymap.key("Numpad7"), Some(0x1));
+        assert_eq!(keymap.key("NumpadDecimal"), Some(0x0));
+
+        let keymap = Keymap::new(Layout::Gamepad);
+        assert!(keymap.keypad(["DPadLeft", "ArrowLeft", "South"])[4]);
+        assert_eq!(keymap.key("Space"), Some(0x5));
+    }
+
+    #[test]
+    fn overrides() {
+        let mut keymap = Keymap::default();
+        keymap
+            .apply_overrides(
+                "# Tetris\n\
+                 ArrowLeft = 5\n\
+                 arrowright=6   # rotate\n\
+                 \n\
+                 KeyW = -\n",
+            )
+            .unwrap();
+        assert_eq!(keymap.key("ArrowLeft"), Some(0x5));
+        assert_eq!(keymap.key("ArrowRight"), Some(0x6));
+        assert_eq!(keymap.key("KeyW"), None);
+        assert_eq!(keymap.key("KeyQ"), Some(0x4));
+
+        keymap.apply_overrides("layout hp48\nKeyQ = a").unwrap();
+        assert_eq!(keymap.key("ArrowLeft"), None);
+        assert_eq!(keymap.key("KeyQ"), Some(0xA));
+
+        for text in ["KeyQ 5", "KeyQ = 10", "layout zx81", "Key Q = 1"] {
+            let e = keymap.apply_overrides(text);
+            assert!(matches!(e, Err(ChipError::InvalidKeymap(_))), "{}", text);
+        }
+
+        // Nothing is applied when a later line fails
+        let before = keymap.clone();
+        let e = keymap.apply_overrides(
+            "layout gamepad
+KeyQ = -
+ArrowUp = 2
+KeyQ 5",
+        );
+        assert!(matches!(e, Err(ChipError::InvalidKeymap(m)) if m.starts_with("line 4")));
+        assert_eq!(keymap, before);
+    }
+}
@@ -2,14 +2,15 @@
 //! focus on the frontend with the graphics library and renderer of your choice.
 //!
 //! # Example
-//! This is a basic skeleton of how to start implementing a frontend. 
+//! This is a basic skeleton of how to start implementing a frontend.
 //! It's recommended to use the [anyhow] crate as well.
 //! ```ignore
-//! use schip8::Chip8; 
+//! use schip8::{Chip8, Palette};
 //! use anyhow::{Context, Result};
 //!
 //! fn main() -> Result<()> {
 //!     let mut chip = Chip8::default();
+//!     let palette = Palette::default();
 //!     
 //!     // The load_file function needs to be implemented
 //!     let file = load_file("roms/TETRIS")?;    
@@ -22,20 +23,17 @@
 //!
 //!         chip.tick().context("Interpreter tick")?;
 //!
-//!         // Render screen - check the examples for scaling demo 
-//!         for y in 0..chip.screen.height {
-//!             for x in 0..chip.screen.width {
-//!                 if chip.screen.get_pixel(x, y) {
-//!                     // Draw
-//!                 }
-//!             }
-//!         }
+//!         // Render the screen, scaled up 8 times, and upload it to a texture
+//!         let (width, height) = chip.screen.scaled_size(8);
+//!         let mut frame = vec![0u32; width * height];
+//!         chip.screen.render_u32(&palette, 8, &mut frame)?;
+//!         // ...
 //!
 //!         // Reset with chip.reset() if reset key is pressed
 //!
 //!         if chip.should_play_sound() {
 //!             // Play a tone
-//!         } 
+//!         }
 //!     }
 //! }
 //! ```
@@ -44,18 +42,56 @@
 //! [here]: https://github.com/overthemil/schip8-macroquad
 //! [anyhow]: https://crates.io/crates/anyhow/
 
+mod assembler;
+mod audio;
+mod cartridge;
+mod clock;
 mod config;
 mod cpu;
+mod database;
+mod debugger;
+mod disassembler;
 mod errors;
+mod gif;
+mod image;
+mod json;
+mod keymap;
 mod memory;
+mod movie;
+mod palette;
+mod quirks;
+mod rewind;
+mod rng;
 mod screen;
+mod sha1;
+mod state;
+mod timing;
+mod trace;
 
-pub use config::Config;
-pub use cpu::Cpu;
+pub use assembler::assemble;
+pub use audio::{Audio, Waveform};
+pub use cartridge::Cartridge;
+pub use config::{Config, OutOfBounds, Platform};
+pub use cpu::{AccessKind, Cpu, KeyEvent, MemoryAccess};
+pub use database::{RomDatabase, RomInfo};
+pub use debugger::{Comparison, Condition, Debugger, Register, StopReason, WatchKind, Watchpoint};
+pub use disassembler::{disassemble, disassemble_instruction, Instruction};
 pub use errors::ChipError;
-pub use screen::Screen;
+pub use gif::GifRecorder;
+pub use keymap::{Keymap, Layout};
+pub use movie::{Movie, Replay};
+pub use palette::Palette;
+pub use quirks::{IndexIncrement, KeyWait, Quirks};
+pub use rng::{ChipRng, XorShiftRng};
+pub use screen::{DirtyRegion, Screen};
+pub use timing::Timing;
+pub use trace::{
+    read_binary_trace, BinarySink, RingBufferSink, TextSink, TraceRecord, TraceSink, TraceState,
+};
 
-const MEMORY_SIZE: usize = 4096;
+use clock::Clock;
+use cpu::Opcode;
+use rewind::RewindBuffer;
 
 /// Represents the CHIP-8 VM that acts as the interpreter.
 pub struct Chip8 {
@@ -63,7 +99,8 @@ pub struct Chip8 {
     ///
     /// The whole range is readable and writable and acts as RAM. The font and rom
     /// data are loaded to specific regions in this memory, typically in the lower addresses.
-    pub memory: [u8; MEMORY_SIZE],
+    /// Its size depends on the [Platform] set in the [Config].
+    pub memory: Vec<u8>,
     /// The display representing the pixels written to by the CPU.
     pub screen: Screen,
     /// Changes various settings of the interpreter. Ability to change the tick rate to
@@ -71,61 +108,185 @@ pub struct Chip8 {
     pub config: Config,
     /// The CPU containing the core of the interpreter.
     pub cpu: Cpu,
+    /// Breakpoints, watchpoints and stepping which control where [`Chip8::tick`] stops.
+    pub debugger: Debugger,
+    /// Renders the sound of every frame to PCM samples when set. Disabled by default.
+    pub audio: Option<Audio>,
+    /// Captures the screen of every frame into an animated GIF when set. Disabled by default.
+    pub gif: Option<GifRecorder>,
     rom: Vec<u8>,
+    frame_cycles: u32,
+    rewind_buffer: RewindBuffer,
+    rng: Box<dyn ChipRng>,
+    seed: u64,
+    recording: Option<Movie>,
+    clock: Clock,
 }
 
 impl Chip8 {
     /// Create a new CHIP-8 interpreter with a custom [Config].
     pub fn new(config: Config) -> Self {
         let mut c8 = Chip8 {
+            memory: vec![0; config.platform.memory_size()],
             config,
             ..Default::default()
         };
         c8.load_default_font();
         c8.cpu.pc = c8.config.rom_base_addr;
+        c8.reseed_rng();
 
         c8
     }
 
     /// Performs a single Fetch-Decode-Execute cycle in the [Cpu].
     pub fn step(&mut self) -> Result<(), ChipError> {
-        self.cpu.step(&mut self.memory, &mut self.screen)?;
+        self.cpu.step(
+            &mut self.memory,
+            &mut self.screen,
+            &self.config,
+            self.rng.as_mut(),
+        )?;
 
         Ok(())
     }
 
     /// Execute a full render cycle. At 60fps, this should be executed 60 times per second.
     ///
-    /// The amount of steps that occurs in each render cycle is determined by the tick rate.
-    pub fn tick(&mut self) -> Result<(), ChipError> {
-        for _ in 0..self.config.tick_rate {
+    /// The amount of steps that occurs in each render cycle is determined by the tick rate,
+    /// or by the cost of each instruction with [`Timing::CosmacVip`].
+    /// When the [Debugger] stops the execution, the timers are not updated and the next
+    /// call continues with the remaining cycles of the frame.
+    pub fn tick(&mut self) -> Result<StopReason, ChipError> {
+        let frame_length = match self.config.timing {
+            Timing::TickRate => self.config.tick_rate,
+            Timing::CosmacVip => timing::VIP_CYCLES_PER_FRAME,
+        };
+
+        while self.frame_cycles < frame_length {
+            if self.cpu.halted {
+                self.frame_cycles = frame_length;
+                break;
+            }
+
+            // Decoded before running so an instruction overwriting itself is
+            // costed as the one that ran
+            let pc = self.cpu.pc;
+            let opcode = self
+                .memory
+                .get(pc..pc.saturating_add(2))
+                .map(|bytes| Opcode::from(u16::from_be_bytes([bytes[0], bytes[1]])));
+            // Drawing waits for the interrupt at the start of the next frame
+            let display_wait = matches!(&opcode, Some(opcode) if opcode.prefix == 0xD);
+            if self.config.timing == Timing::CosmacVip && display_wait && self.frame_cycles > 0 {
+                self.frame_cycles = frame_length;
+                break;
+            }
+
+            if let Some(reason) = self.debugger.check_before(&self.cpu) {
+                return Ok(reason);
+            }
             self.step()?;
+            self.frame_cycles += match (self.config.timing, opcode) {
+                (Timing::CosmacVip, Some(opcode)) => self.vip_cycles(pc, &opcode),
+                _ => 1,
+            };
+
+            if let Some(reason) = self.debugger.check_after(&self.cpu) {
+                return Ok(reason);
+            }
+        }
+        // The cycles of the instruction that ran past the end of the frame are
+        // taken from the next one
+        self.frame_cycles -= frame_length.min(self.frame_cycles);
+        self.end_frame();
+
+        match self.cpu.halted {
+            true => Ok(StopReason::Halted),
+            false => Ok(StopReason::FrameComplete),
         }
+    }
+
+    // The cycles taken by the instruction that was at the address
+    fn vip_cycles(&self, pc: usize, opcode: &Opcode) -> u32 {
+        let skipped = matches!(opcode.prefix, 0x3 | 0x4 | 0x5 | 0x9 | 0xE) && self.cpu.pc != pc + 2;
+
+        timing::vip_cycles(opcode, &self.cpu, skipped)
+    }
 
+    // Renders the sound, captures the screen, counts down the timers, records the
+    // frame for rewinding and movies and drops the key events of the frame
+    fn end_frame(&mut self) {
+        self.render_audio_frame();
+        self.capture_gif_frame();
         if self.cpu.timer_delay > 0 {
             self.cpu.timer_delay -= 1;
         }
         if self.cpu.timer_sound > 0 {
             self.cpu.timer_sound -= 1;
         }
-
-        Ok(())
+        self.record_movie_frame();
+        self.cpu.clear_key_events();
+        self.record_rewind_frame();
     }
 
     /// Sets the machine as if newly created. Any changed configs and loaded ROMs persist.
     pub fn reset(&mut self) {
-        self.screen.clear_screen();
+        self.screen.set_hires(false);
+        self.screen.select_planes(0x1);
         self.reset_memory();
         self.cpu.reset();
         self.cpu.pc = self.config.rom_base_addr;
+        self.frame_cycles = 0;
+        self.clock = Clock::default();
+        self.rewind_buffer.clear();
+        self.reseed_rng();
+    }
+
+    /// Replace the source of random numbers. It is reseeded right away with the seed
+    /// from the [Config] so the machine stays deterministic.
+    pub fn set_rng(&mut self, rng: Box<dyn ChipRng>) {
+        self.rng = rng;
+        self.reseed_rng();
+    }
+
+    /// The seed the random number generator was last seeded with. When no seed is set
+    /// in the [Config], this is the one picked at random.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn reseed_rng(&mut self) {
+        self.seed = self.config.seed.unwrap_or_else(rand::random);
+        self.rng.reseed(self.seed);
     }
 
-    /// Set any of the keys in the keypad (0x0 - 0xF) as pressed.
+    /// Set any of the keys in the keypad (0x0 - 0xF) as pressed. The keys that changed
+    /// since the last call are queued as if pressed or released with [`Chip8::key_down`]
+    /// and [`Chip8::key_up`].
     pub fn set_input(&mut self, keys_pressed: [bool; 16]) {
-        self.cpu.keypad = keys_pressed;
+        for (key, &pressed) in keys_pressed.iter().enumerate() {
+            match pressed {
+                true => self.cpu.key_down(key as u8),
+                false => self.cpu.key_up(key as u8),
+            }
+            .expect("the keypad has 16 keys");
+        }
+    }
+
+    /// Press a key of the keypad (0x0 - 0xF). The press is queued for the instructions
+    /// waiting for a key during the next frame, so a key pressed and released between
+    /// two frames is not missed.
+    pub fn key_down(&mut self, key: u8) -> Result<(), ChipError> {
+        self.cpu.key_down(key)
     }
 
-    /// Announces if a tone should be played.
+    /// Release a key of the keypad (0x0 - 0xF), see [`Chip8::key_down`].
+    pub fn key_up(&mut self, key: u8) -> Result<(), ChipError> {
+        self.cpu.key_up(key)
+    }
+
+    /// Announces if a tone should be played. Set [`Chip8::audio`] to have the tone
+    /// rendered instead.
     pub fn should_play_sound(&self) -> bool {
         self.cpu.timer_sound > 0
     }
@@ -133,15 +294,26 @@ impl Chip8 {
 
 impl Default for Chip8 {
     fn default() -> Self {
+        let config = Config::default();
         let mut c8 = Chip8 {
-            memory: [0; MEMORY_SIZE],
+            memory: vec![0; config.platform.memory_size()],
             screen: Screen::default(),
-            config: Config::default(),
+            config,
             cpu: Cpu::default(),
+            debugger: Debugger::default(),
+            audio: None,
+            gif: None,
             rom: Vec::new(),
+            frame_cycles: 0,
+            rewind_buffer: RewindBuffer::default(),
+            rng: Box::new(XorShiftRng::new(0)),
+            seed: 0,
+            recording: None,
+            clock: Clock::default(),
         };
         c8.load_default_font();
         c8.cpu.pc = c8.config.rom_base_addr;
+        c8.reseed_rng();
 
         c8
     }
@@ -0,0 +1,497 @@
+//! Headless runner that executes a ROM without any display and dumps the final
+//! state of the machine, for smoke testing ROMs in CI.
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use schip8::{
+    Chip8, Comparison, Condition, Config, GifRecorder, KeyEvent, Movie, Palette, Platform, Quirks,
+    Register, StopReason, TextSink, Timing,
+};
+
+const USAGE: &str = "\
+Usage: schip8 [OPTIONS] <ROM>
+
+Runs a ROM without a display and prints the registers and the screen once it stops.
+
+Options:
+  --frames <N>           Stop after N frames [default: 600]
+  --until-pc <ADDR>      Stop when the PC reaches ADDR
+  --until <COND>         Stop at the end of the first frame where COND holds, e.g. V3==0x10
+  --keys <FRAME>=<KEYS>  Hold KEYS (hex digits, or - for none) from FRAME onwards
+  --script <FILE>        Read --keys entries from FILE, one `FRAME KEYS` per line
+  --movie <FILE>         Play back a movie file, with the settings it was recorded with
+  --platform <NAME>      chip8 or xochip [default: chip8]
+  --quirks <NAME>        legacy, vip, chip48, schip10, schip11 or xochip [default: legacy]
+  --tick-rate <N>        Instructions per frame
+  --timing <MODE>        tick-rate or vip for the COSMAC VIP instruction timings [default: tick-rate]
+  --seed <N>             Seed of the random number generator
+  --screen <FORMAT>      Dump the screen as text, ppm or png [default: text]
+  --output <FILE>        Write the screen to FILE instead of the standard output
+  --scale <N>            Draw every pixel of the ppm, png and gif images as NxN [default: 1]
+  --gif <FILE>           Record every frame into an animated GIF
+  --trace <FILE>         Write every executed instruction to FILE
+  -h, --help             Print this help";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScreenFormat {
+    Text,
+    Ppm,
+    Png,
+}
+
+struct Options {
+    rom: String,
+    frames: u64,
+    until_pc: Option<usize>,
+    until: Option<Condition>,
+    // Frames at which the held keys change, sorted by frame
+    keys: Vec<(u64, u16)>,
+    movie: Option<String>,
+    platform: Platform,
+    quirks: Quirks,
+    tick_rate: Option<u32>,
+    timing: Timing,
+    seed: Option<u64>,
+    screen: ScreenFormat,
+    output: Option<String>,
+    scale: usize,
+    gif: Option<String>,
+    trace: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let result = parse_args(&args).and_then(|options| run(&options));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = fs::read(&options.rom).map_err(|e| format!("{}: {}", options.rom, e))?;
+
+    let mut config = Config {
+        platform: options.platform,
+        quirks: options.quirks,
+        timing: options.timing,
+        seed: options.seed,
+        ..Default::default()
+    };
+    if let Some(tick_rate) = options.tick_rate {
+        config.tick_rate = tick_rate;
+    }
+
+    let movie = match &options.movie {
+        Some(path) => {
+            let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            Some(Movie::from_bytes(&data).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+
+    let mut chip = match &movie {
+        Some(movie) => movie.replay(&rom).map_err(|e| e.to_string())?.chip,
+        None => {
+            let mut chip = Chip8::new(config);
+            chip.load_rom(&rom).map_err(|e| e.to_string())?;
+            chip
+        }
+    };
+    if let Some(pc) = options.until_pc {
+        chip.debugger.add_breakpoint(pc);
+    }
+    let trace = match &options.trace {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            let sink = Rc::new(RefCell::new(TextSink::new(BufWriter::new(file))));
+            chip.cpu.set_tracer(Some(Box::new(sink.clone())));
+            Some((path, sink))
+        }
+        None => None,
+    };
+    if options.gif.is_some() {
+        chip.gif = Some(GifRecorder::new(Palette::default(), options.scale));
+    }
+
+    let mut frame = 0;
+    let mut keys = 0;
+    let stop = loop {
+        if frame >= options.frames {
+            break format!("ran {} frames", frame);
+        }
+
+        match &movie {
+            Some(movie) => {
+                for event in movie.frames.get(frame as usize).into_iter().flatten() {
+                    match *event {
+                        KeyEvent::Down(key) => chip.key_down(key),
+                        KeyEvent::Up(key) => chip.key_up(key),
+                    }
+                    .map_err(|e| e.to_string())?;
+                }
+            }
+            None => {
+                let next_keys = options
+                    .keys
+                    .iter()
+                    .rev()
+                    .find(|(from, _)| *from <= frame)
+                    .map(|&(_, keys)| keys);
+                keys = next_keys.unwrap_or(keys);
+                chip.set_input(keys_to_keypad(keys));
+            }
+        }
+
+        match chip.tick().map_err(|e| format!("frame {}: {}", frame, e))? {
+            StopReason::FrameComplete => frame += 1,
+            StopReason::Halted => break format!("halted after {} frames", frame + 1),
+            StopReason::Breakpoint(pc) => {
+                break format!("reached {:#06x} during frame {}", pc, frame)
+            }
+            // Only breakpoints are set
+            StopReason::Watchpoint(_) | StopReason::StepComplete => continue,
+        }
+
+        if let Some(condition) = &options.until {
+            if condition.is_met(&chip.cpu) {
+                break format!("condition met after {} frames", frame);
+            }
+        }
+    };
+
+    if let Some((path, sink)) = trace {
+        drop(chip.cpu.take_tracer());
+        if let Ok(sink) = Rc::try_unwrap(sink) {
+            sink.into_inner()
+                .finish()
+                .map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+
+    if let (Some(path), Some(gif)) = (&options.gif, &chip.gif) {
+        fs::write(path, gif.to_gif()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    let mut out = io::stdout().lock();
+    let write_error = |e: io::Error| e.to_string();
+    writeln!(out, "Stopped: {}", stop).map_err(write_error)?;
+    write_registers(&mut out, &chip).map_err(write_error)?;
+
+    let screen = match options.screen {
+        ScreenFormat::Text => screen_text(&chip).into_bytes(),
+        ScreenFormat::Ppm => chip.screen.to_ppm(&Palette::default(), options.scale),
+        ScreenFormat::Png => chip.screen.to_png(&Palette::default(), options.scale),
+    };
+    match &options.output {
+        Some(path) => fs::write(path, screen).map_err(|e| format!("{}: {}", path, e))?,
+        None => out.write_all(&screen).map_err(write_error)?,
+    }
+
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        frames: 600,
+        until_pc: None,
+        until: None,
+        keys: Vec::new(),
+        movie: None,
+        platform: Platform::Chip8,
+        quirks: Quirks::default(),
+        tick_rate: None,
+        timing: Timing::TickRate,
+        seed: None,
+        screen: ScreenFormat::Text,
+        output: None,
+        scale: 1,
+        gif: None,
+        trace: None,
+    };
+    let mut rom = None;
+    // The movie holds the settings and the input of the machine
+    let mut movie_conflicts = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.replace(arg.clone()).is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        if let "--keys" | "--script" | "--platform" | "--quirks" | "--tick-rate" | "--timing"
+        | "--seed" = arg.as_str()
+        {
+            movie_conflicts.push(arg);
+        }
+        match arg.as_str() {
+            "--frames" => options.frames = parse_number(value)?,
+            "--until-pc" => options.until_pc = Some(parse_number(value)?),
+            "--until" => options.until = Some(parse_condition(value)?),
+            "--keys" => {
+                let (frame, keys) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected FRAME=KEYS, got '{}'", value))?;
+                options.keys.push((parse_number(frame)?, parse_keys(keys)?));
+            }
+            "--script" => {
+                let script = fs::read_to_string(value).map_err(|e| format!("{}: {}", value, e))?;
+                options.keys.extend(parse_script(&script)?);
+            }
+            "--movie" => options.movie = Some(value.clone()),
+            "--platform" => {
+                options.platform = match value.as_str() {
+                    "chip8" => Platform::Chip8,
+                    "xochip" => Platform::XoChip,
+                    _ => return Err(format!("unknown platform '{}'", value)),
+                }
+            }
+            "--quirks" => {
+                options.quirks = match value.as_str() {
+                    "legacy" => Quirks::default(),
+                    "vip" => Quirks::COSMAC_VIP,
+                    "chip48" => Quirks::CHIP_48,
+                    "schip10" => Quirks::SCHIP_1_0,
+                    "schip11" => Quirks::SCHIP_1_1,
+                    "xochip" => Quirks::XO_CHIP,
+                    _ => return Err(format!("unknown quirks '{}'", value)),
+                }
+            }
+            "--tick-rate" => options.tick_rate = Some(parse_number(value)?),
+            "--timing" => {
+                options.timing = match value.as_str() {
+                    "tick-rate" => Timing::TickRate,
+                    "vip" => Timing::CosmacVip,
+                    _ => return Err(format!("unknown timing '{}'", value)),
+                }
+            }
+            "--seed" => options.seed = Some(parse_number(value)?),
+            "--screen" => {
+                options.screen = match value.as_str() {
+                    "text" => ScreenFormat::Text,
+                    "ppm" => ScreenFormat::Ppm,
+                    "png" => ScreenFormat::Png,
+                    _ => return Err(format!("unknown screen format '{}'", value)),
+                }
+            }
+            "--output" => options.output = Some(value.clone()),
+            "--scale" => {
+                options.scale = parse_number(value)?;
+                if options.scale == 0 {
+                    return Err("the scale must be at least 1".to_string());
+                }
+            }
+            "--gif" => options.gif = Some(value.clone()),
+            "--trace" => options.trace = Some(value.clone()),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    if let (Some(_), Some(option)) = (&options.movie, movie_conflicts.first()) {
+        return Err(format!("{} can't be used with --movie", option));
+    }
+    options.rom = rom.ok_or_else(|| format!("missing ROM\n\n{}", USAGE))?;
+    options.keys.sort_by_key(|&(frame, _)| frame);
+
+    Ok(options)
+}
+
+// Decimal, or hexadecimal with a 0x prefix
+fn parse_number<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    parsed
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("invalid number '{}'", value))
+}
+
+// A register, a comparison and a value, e.g. `V3==0x10` or `I>0x300`
+fn parse_condition(value: &str) -> Result<Condition, String> {
+    let invalid = || format!("invalid condition '{}'", value);
+
+    let (register, comparison, number) = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<", Comparison::LessThan),
+        (">", Comparison::GreaterThan),
+    ]
+    .into_iter()
+    .find_map(|(op, comparison)| {
+        value
+            .split_once(op)
+            .map(|(register, number)| (register, comparison, number))
+    })
+    .ok_or_else(invalid)?;
+
+    let register = match register.trim().to_ascii_uppercase().as_str() {
+        "I" => Register::I,
+        "SP" => Register::Sp,
+        "DT" => Register::DelayTimer,
+        "ST" => Register::SoundTimer,
+        r => match r.strip_prefix('V').map(|n| u8::from_str_radix(n, 16)) {
+            Some(Ok(n)) if n < 16 => Register::V(n),
+            _ => return Err(invalid()),
+        },
+    };
+
+    Ok(Condition::new(
+        register,
+        comparison,
+        parse_number(number.trim())?,
+    ))
+}
+
+// Hex digits of the held keys, or - for none
+fn parse_keys(value: &str) -> Result<u16, String> {
+    if value == "-" {
+        return Ok(0);
+    }
+
+    value.chars().try_fold(0, |keys, c| {
+        c.to_digit(16)
+            .map(|key| keys | (1 << key))
+            .ok_or_else(|| format!("invalid key '{}'", c))
+    })
+}
+
+fn parse_script(script: &str) -> Result<Vec<(u64, u16)>, String> {
+    script
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [frame, keys] => Ok((parse_number(frame)?, parse_keys(keys)?)),
+                _ => Err(format!("expected `FRAME KEYS`, got '{}'", line)),
+            },
+        )
+        .collect()
+}
+
+fn keys_to_keypad(keys: u16) -> [bool; 16] {
+    std::array::from_fn(|key| keys & (1 << key) != 0)
+}
+
+fn write_registers(out: &mut impl Write, chip: &Chip8) -> io::Result<()> {
+    let cpu = &chip.cpu;
+    writeln!(
+        out,
+        "PC: {:#06x}  I: {:#06x}  SP: {:#04x}  DT: {:#04x}  ST: {:#04x}",
+        cpu.pc, cpu.i, cpu.sp, cpu.timer_delay, cpu.timer_sound
+    )?;
+    for row in cpu.v.chunks(8).enumerate() {
+        let (start, values) = row;
+        let line: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X}: {:#04x}", start * 8 + i, v))
+            .collect();
+        writeln!(out, "{}", line.join("  "))?;
+    }
+    let stack: Vec<String> = cpu.stack[1..=cpu.sp]
+        .iter()
+        .map(|addr| format!("{:#06x}", addr))
+        .collect();
+    writeln!(out, "Stack: [{}]", stack.join(", "))
+}
+
+// One character per pixel: '.' when off, '#' when on and the color index when
+// more than one plane is drawn
+fn screen_text(chip: &Chip8) -> String {
+    let screen = &chip.screen;
+    let mut text = String::with_capacity((screen.width + 1) * screen.height);
+    for y in 0..screen.height {
+        for x in 0..screen.width {
+            text.push(match screen.get_pixel(x, y) {
+                0 => '.',
+                1 => '#',
+                c => char::from_digit(c as u32, 16).unwrap_or('?'),
+            });
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options() {
+        let options = parse_args(&args(
+            "--frames 0x10 rom.ch8 --keys 5=2a --keys 2=- --quirks vip --screen ppm",
+        ))
+        .unwrap();
+        assert_eq!(options.rom, "rom.ch8");
+        assert_eq!(options.frames, 16);
+        assert_eq!(options.keys, vec![(2, 0), (5, 0b0100_0000_0100)]);
+        assert_eq!(options.quirks, Quirks::COSMAC_VIP);
+        assert_eq!(options.screen, ScreenFormat::Ppm);
+
+        assert!(parse_args(&args("--frames")).is_err());
+        assert!(parse_args(&args("--frames 10")).is_err());
+        assert!(parse_args(&args("a.ch8 b.ch8")).is_err());
+        assert!(parse_args(&args("a.ch8 --bogus 1")).is_err());
+
+        assert!(parse_args(&args("a.ch8 --movie a.sc8m --frames 5")).is_ok());
+        for option in ["--seed 1", "--tick-rate 20", "--quirks vip", "--keys 0=5"] {
+            let e = parse_args(&args(&format!("a.ch8 --movie a.sc8m {}", option)));
+            assert!(e.is_err(), "{}", option);
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let condition = parse_condition("vA==0x10").unwrap();
+        assert_eq!(
+            condition,
+            Condition::new(Register::V(0xA), Comparison::Equal, 0x10)
+        );
+        let condition = parse_condition("I > 768").unwrap();
+        assert_eq!(
+            condition,
+            Condition::new(Register::I, Comparison::GreaterThan, 768)
+        );
+
+        assert!(parse_condition("VG==1").is_err());
+        assert!(parse_condition("V1=1").is_err());
+    }
+
+    #[test]
+    fn script() {
+        let keys = parse_script("# Start the game\n0 -\n30 5  # jump\n\n31 56\n").unwrap();
+        assert_eq!(keys, vec![(0, 0), (30, 0x20), (31, 0x60)]);
+
+        assert!(parse_script("30").is_err());
+        assert!(parse_script("30 x").is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use crate::errors::ChipError;
-use crate::MEMORY_SIZE;
 use crate::Chip8;
 
 pub const FONT_BASE_ADDRESS: usize = 0x050;
+pub const BIG_FONT_BASE_ADDRESS: usize = 0x0A0;
 
 const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -23,10 +23,29 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 impl Chip8 {
     /// Write a byte of data to the address specified.
     pub fn write(&mut self, address: usize, data: u8) -> Result<(), ChipError> {
-        if address >= MEMORY_SIZE {
+        if address >= self.memory.len() {
             return Err(ChipError::AddressOutOfBounds {
                 address,
                 limit: self.memory.len(),
@@ -40,7 +59,7 @@ impl Chip8 {
 
     /// Read a byte of data from the address specified.
     pub fn read(&self, address: usize) -> Result<u8, ChipError> {
-        if address >= MEMORY_SIZE {
+        if address >= self.memory.len() {
             return Err(ChipError::AddressOutOfBounds {
                 address,
                 limit: self.memory.len(),
@@ -52,13 +71,15 @@ impl Chip8 {
 
     /// Write an array of bytes to memory starting at the base address.
     pub fn load(&mut self, base_address: usize, data: &[u8]) -> Result<(), ChipError> {
-        let end_address = base_address + data.len();
-        if (end_address) >= MEMORY_SIZE {
-            return Err(ChipError::AddressOutOfBounds {
-                address: end_address,
-                limit: self.memory.len(),
-            });
-        }
+        let end_address = match base_address.checked_add(data.len()) {
+            Some(end) if end < self.memory.len() => end,
+            end => {
+                return Err(ChipError::AddressOutOfBounds {
+                    address: end.unwrap_or(usize::MAX),
+                    limit: self.memory.len(),
+                })
+            }
+        };
 
         self.memory[base_address..end_address].copy_from_slice(data);
 
@@ -80,14 +101,25 @@ impl Chip8 {
         Ok(())
     }
 
-    /// Write the default font to memory.
+    /// Write an array of bytes to memory starting at the big font base address.
+    ///
+    /// The big font is used by the Super-Chip FX30 instruction and each character
+    /// is 8x10 pixels.
+    pub fn load_big_font(&mut self, font: &[u8]) -> Result<(), ChipError> {
+        self.load(BIG_FONT_BASE_ADDRESS, font)?;
+
+        Ok(())
+    }
+
+    /// Write the default fonts to memory.
     pub fn load_default_font(&mut self) {
         let _ = self.load_font(&FONT);
+        let _ = self.load_big_font(&BIG_FONT);
     }
 
     /// Set all values in memory to zero, reload default font and last loaded ROM.
     pub fn reset_memory(&mut self) {
-        self.memory = [0; MEMORY_SIZE];
+        self.memory = vec![0; self.config.platform.memory_size()];
         self.load_default_font();
         let rom_data = self.rom.clone();
         let _ = self.load(self.config.rom_base_addr, &rom_data);
@@ -99,6 +131,7 @@ mod tests {
     use super::*;
     use crate::Chip8;
     use crate::ChipError;
+    use crate::{Config, Platform};
 
     #[test]
     fn write() {
@@ -117,6 +150,8 @@ mod tests {
 
         let val = c8.read(FONT_BASE_ADDRESS).unwrap();
         assert_eq!(val, 0xF0);
+        let val = c8.read(BIG_FONT_BASE_ADDRESS).unwrap();
+        assert_eq!(val, 0x3C);
         let val = c8.read(0x200).unwrap();
         assert_eq!(val, 0);
 
@@ -138,5 +173,33 @@ mod tests {
 
         let e = c8.load(4091, &[1, 2, 3, 4, 5]);
         assert!(matches!(e, Err(ChipError::AddressOutOfBounds { .. })));
+        let e = c8.load(usize::MAX, &[1, 2]);
+        assert!(matches!(e, Err(ChipError::AddressOutOfBounds { .. })));
+
+        // A start address from a corrupt database entry
+        c8.config.rom_base_addr = usize::MAX;
+        c8.reset();
+        let e = c8.load_rom(&[0x12, 0x00]);
+        assert!(matches!(e, Err(ChipError::AddressOutOfBounds { .. })));
+        let e = c8.step();
+        assert!(matches!(e, Err(ChipError::AddressOutOfBounds { .. })));
+    }
+
+    #[test]
+    fn xo_chip_memory() {
+        let mut c8 = Chip8::new(Config {
+            platform: Platform::XoChip,
+            ..Default::default()
+        });
+        assert_eq!(c8.memory.len(), 65536);
+
+        c8.write(0xFFFF, 1).unwrap();
+        c8.reset_memory();
+        assert_eq!(c8.memory.len(), 65536);
+        assert_eq!(c8.memory[0xFFFF], 0);
+
+        c8.config.platform = Platform::Chip8;
+        c8.reset_memory();
+        assert_eq!(c8.memory.len(), 4096);
     }
 }
@@ -0,0 +1,436 @@
+use crate::sha1::sha1;
+use crate::state::{StateReader, StateWriter};
+use crate::{
+    Chip8, ChipError, Config, IndexIncrement, KeyEvent, KeyWait, OutOfBounds, Platform, Quirks,
+    StopReason, Timing,
+};
+
+const MAGIC: &[u8; 4] = b"SC8M";
+const VERSION: u8 = 1;
+
+// A day of frames. Movies claiming more are corrupt, and the frame count is checked
+// before the frames are allocated.
+const MAX_FRAMES: usize = 60 * 60 * 60 * 24;
+
+/// A recording of the key events of every frame along with everything needed
+/// to play it back exactly: the [Config], the seed of the random number
+/// generator and the SHA-1 of the ROM.
+///
+/// Record one with [`Chip8::start_recording`] and play it back with [`Movie::replay`].
+#[derive(Clone, Debug)]
+pub struct Movie {
+    /// The SHA-1 of the ROM the movie was recorded with.
+    pub rom_hash: [u8; 20],
+    /// The seed of the random number generator.
+    pub seed: u64,
+    /// The settings of the interpreter during the recording.
+    pub config: Config,
+    /// The keys pressed and released before each frame, in the order they happened,
+    /// so a key tapped between two frames is played back too.
+    pub frames: Vec<Vec<KeyEvent>>,
+}
+
+impl Movie {
+    /// Serialize the movie. Only the frames with key events are stored to keep the
+    /// file small.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter { data: Vec::new() };
+        w.data.extend_from_slice(MAGIC);
+        w.u8(VERSION);
+        w.data.extend_from_slice(&self.rom_hash);
+        w.u64(self.seed);
+        write_config(&mut w, &self.config);
+
+        w.u32(self.frames.len() as u32);
+        let active: Vec<(usize, &Vec<KeyEvent>)> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, events)| !events.is_empty())
+            .collect();
+        w.u32(active.len() as u32);
+        for (frame, events) in active {
+            w.u32(frame as u32);
+            w.key_events(events.iter());
+        }
+
+        w.data
+    }
+
+    /// Parse a movie serialized by [`Movie::to_bytes`].
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, ChipError> {
+        let mut r = StateReader::new(data, ChipError::InvalidMovie);
+
+        if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(ChipError::InvalidMovie("not a movie".to_string()));
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(ChipError::InvalidMovie(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let rom_hash = r.array()?;
+        let seed = r.u64()?;
+        let config = read_config(&mut r)?;
+
+        let frame_count = r.u32()? as usize;
+        if frame_count > MAX_FRAMES {
+            return Err(ChipError::InvalidMovie(format!(
+                "{} frames is more than the limit of {}",
+                frame_count, MAX_FRAMES
+            )));
+        }
+        let active = r.u32()?;
+        let mut frames = Vec::new();
+        for _ in 0..active {
+            let frame = r.u32()? as usize;
+            if frame < frames.len() || frame >= frame_count {
+                return Err(ChipError::InvalidMovie(format!("invalid frame {}", frame)));
+            }
+            frames.resize(frame, Vec::new());
+            frames.push(r.key_events()?);
+        }
+        frames.resize(frame_count, Vec::new());
+
+        if !r.is_empty() {
+            return Err(ChipError::InvalidMovie(
+                "unexpected data at the end".to_string(),
+            ));
+        }
+
+        Ok(Movie {
+            rom_hash,
+            seed,
+            config,
+            frames,
+        })
+    }
+
+    /// Create a machine running the ROM with the settings of the movie, ready to play
+    /// back its frames. Fails with [`ChipError::MovieRomMismatch`] when the ROM is not
+    /// the one the movie was recorded with.
+    pub fn replay(&self, rom: &[u8]) -> Result<Replay<'_>, ChipError> {
+        if sha1(rom) != self.rom_hash {
+            return Err(ChipError::MovieRomMismatch);
+        }
+
+        let mut config = self.config.clone();
+        config.seed = Some(self.seed);
+        let mut chip = Chip8::new(config);
+        chip.load_rom(rom)?;
+
+        Ok(Replay {
+            chip,
+            movie: self,
+            frame: 0,
+        })
+    }
+}
+
+/// Plays back a [Movie] frame by frame without any frontend.
+pub struct Replay<'a> {
+    /// The machine running the movie. Inspect it between frames.
+    pub chip: Chip8,
+    movie: &'a Movie,
+    frame: usize,
+}
+
+impl Replay<'_> {
+    /// Run the next frame after the key events recorded for it. Returns false once
+    /// every frame of the movie has been played.
+    pub fn next_frame(&mut self) -> Result<bool, ChipError> {
+        let Some(events) = self.movie.frames.get(self.frame) else {
+            return Ok(false);
+        };
+
+        for event in events {
+            match *event {
+                KeyEvent::Down(key) => self.chip.key_down(key)?,
+                KeyEvent::Up(key) => self.chip.key_up(key)?,
+            }
+        }
+        while !matches!(
+            self.chip.tick()?,
+            StopReason::FrameComplete | StopReason::Halted
+        ) {}
+        self.frame += 1;
+
+        Ok(true)
+    }
+
+    /// Play all the remaining frames.
+    pub fn run_to_end(&mut self) -> Result<(), ChipError> {
+        while self.next_frame()? {}
+
+        Ok(())
+    }
+
+    /// How many frames have been played so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Whether every frame of the movie has been played.
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+impl Chip8 {
+    /// Reset the machine and start recording the key events of every frame. The
+    /// recording picks up the current [Config] and the seed of the random number
+    /// generator, so set those first. Movies hold up to a day of frames, the frames
+    /// after that are not recorded.
+    pub fn start_recording(&mut self) {
+        self.reset();
+        self.recording = Some(Movie {
+            rom_hash: sha1(&self.rom),
+            seed: self.seed,
+            config: self.config.clone(),
+            frames: Vec::new(),
+        });
+    }
+
+    /// Stop recording and return the movie. Returns None if nothing was being recorded.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+
+    /// Whether the frames are being recorded into a [Movie].
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub(crate) fn record_movie_frame(&mut self) {
+        if let Some(movie) = self
+            .recording
+            .as_mut()
+            .filter(|movie| movie.frames.len() < MAX_FRAMES)
+        {
+            movie.frames.push(self.cpu.frame_key_events().to_vec());
+        }
+    }
+}
+
+fn write_config(w: &mut StateWriter, config: &Config) {
+    w.u32(config.rom_base_addr as u32);
+    w.u32(config.tick_rate);
+    w.u8(match config.platform {
+        Platform::Chip8 => 0,
+        Platform::XoChip => 1,
+    });
+    w.u8(match config.out_of_bounds {
+        OutOfBounds::Error => 0,
+        OutOfBounds::Wrap => 1,
+    });
+    w.u8(match config.timing {
+        Timing::TickRate => 0,
+        Timing::CosmacVip => 1,
+    });
+
+    let quirks = &config.quirks;
+    w.u8(quirks.shift_uses_vy as u8);
+    w.u8(match quirks.load_store_index {
+        IndexIncrement::Unchanged => 0,
+        IndexIncrement::ByX => 1,
+        IndexIncrement::ByXPlusOne => 2,
+    });
+    w.u8(quirks.jump_uses_vx as u8);
+    w.u8(quirks.logic_resets_vf as u8);
+    w.u8(quirks.clip_sprites as u8);
+    w.u8(match quirks.key_wait {
+        KeyWait::Held => 0,
+        KeyWait::Press => 1,
+        KeyWait::Release => 2,
+    });
+}
+
+fn read_config(r: &mut StateReader) -> Result<Config, ChipError> {
+    let rom_base_addr = r.u32()? as usize;
+    let tick_rate = r.u32()?;
+    let platform = match r.u8()? {
+        0 => Platform::Chip8,
+        1 => Platform::XoChip,
+        p => return Err(ChipError::InvalidMovie(format!("unknown platform {}", p))),
+    };
+    let out_of_bounds = match r.u8()? {
+        0 => OutOfBounds::Error,
+        1 => OutOfBounds::Wrap,
+        p => {
+            return Err(ChipError::InvalidMovie(format!(
+                "unknown out of bounds policy {}",
+                p
+            )))
+        }
+    };
+    let timing = match r.u8()? {
+        0 => Timing::TickRate,
+        1 => Timing::CosmacVip,
+        t => return Err(ChipError::InvalidMovie(format!("unknown timing {}", t))),
+    };
+
+    let shift_uses_vy = r.bool()?;
+    let load_store_index = match r.u8()? {
+        0 => IndexIncrement::Unchanged,
+        1 => IndexIncrement::ByX,
+        2 => IndexIncrement::ByXPlusOne,
+        i => {
+            return Err(ChipError::InvalidMovie(format!(
+                "unknown index increment {}",
+                i
+            )))
+        }
+    };
+    let jump_uses_vx = r.bool()?;
+    let logic_resets_vf = r.bool()?;
+    let clip_sprites = r.bool()?;
+    let key_wait = match r.u8()? {
+        0 => KeyWait::Held,
+        1 => KeyWait::Press,
+        2 => KeyWait::Release,
+        k => return Err(ChipError::InvalidMovie(format!("unknown key wait {}", k))),
+    };
+    let quirks = Quirks {
+        shift_uses_vy,
+        load_store_index,
+        jump_uses_vx,
+        logic_resets_vf,
+        clip_sprites,
+        key_wait,
+    };
+
+    Ok(Config {
+        rom_base_addr,
+        tick_rate,
+        platform,
+        quirks,
+        out_of_bounds,
+        timing,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws a random digit at a position moved by the keys, forever
+    const ROM: [u8; 20] = [
+        0x00, 0xE0, // 0x200: CLS
+        0xC2, 0x0F, // 0x202: RND V2, 0x0F
+        0xF2, 0x29, // 0x204: LD F, V2
+        0x63, 0x05, // 0x206: LD V3, 5
+        0xE3, 0x9E, // 0x208: SKP V3
+        0x70, 0x01, // 0x20A: ADD V0, 1
+        0x63, 0x06, // 0x20C: LD V3, 6
+        0xE3, 0x9E, // 0x20E: SKP V3
+        0x71, 0x01, // 0x210: ADD V1, 1
+        0xD0, 0x15, // 0x212: DRW V0, V1, 5
+    ];
+
+    fn record() -> (Movie, Vec<u8>, Chip8) {
+        let mut rom = ROM.to_vec();
+        rom.extend_from_slice(&[0x12, 0x00]);
+
+        let mut c8 = Chip8::new(Config {
+            tick_rate: 11,
+            quirks: Quirks::COSMAC_VIP,
+            ..Default::default()
+        });
+        c8.load_rom(&rom).unwrap();
+        c8.start_recording();
+        for frame in 0..40 {
+            let mut keys = [false; 16];
+            keys[5] = frame % 3 == 0;
+            keys[6] = frame > 20;
+            c8.set_input(keys);
+            c8.tick().unwrap();
+        }
+
+        (c8.stop_recording().unwrap(), rom, c8)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let (movie, rom, original) = record();
+        assert_eq!(movie.frames.len(), 40);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.config.quirks, Quirks::COSMAC_VIP);
+        assert_eq!(movie.config.tick_rate, 11);
+
+        let mut replay = movie.replay(&rom).unwrap();
+        replay.run_to_end().unwrap();
+        assert!(replay.is_finished());
+        assert_eq!(replay.frame(), 40);
+        assert_eq!(replay.chip.cpu.v, original.cpu.v);
+        assert_eq!(replay.chip.screen.pixels(), original.screen.pixels());
+        assert!(!replay.next_frame().unwrap());
+    }
+
+    #[test]
+    fn tap_within_a_frame() {
+        // Waits for a key, then loops forever
+        let rom = [0xF0, 0x0A, 0x12, 0x02];
+
+        let mut c8 = Chip8::new(Config {
+            tick_rate: 11,
+            quirks: Quirks::COSMAC_VIP,
+            ..Default::default()
+        });
+        c8.load_rom(&rom).unwrap();
+        c8.start_recording();
+        c8.tick().unwrap();
+        c8.key_down(0x7).unwrap();
+        c8.key_up(0x7).unwrap();
+        c8.tick().unwrap();
+        c8.tick().unwrap();
+        assert_eq!(c8.cpu.v[0], 0x7);
+
+        let movie = Movie::from_bytes(&c8.stop_recording().unwrap().to_bytes()).unwrap();
+        assert_eq!(
+            movie.frames,
+            [vec![], vec![KeyEvent::Down(0x7), KeyEvent::Up(0x7)], vec![]]
+        );
+
+        let mut replay = movie.replay(&rom).unwrap();
+        replay.run_to_end().unwrap();
+        assert_eq!(replay.chip.cpu.v[0], 0x7);
+        assert_eq!(replay.chip.cpu.pc, c8.cpu.pc);
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let (movie, mut rom, _) = record();
+        rom[0] = 0x12;
+
+        let e = movie.replay(&rom);
+        assert!(matches!(e, Err(ChipError::MovieRomMismatch)));
+    }
+
+    #[test]
+    fn invalid_movie() {
+        let (movie, _, _) = record();
+        let bytes = movie.to_bytes();
+
+        let e = Movie::from_bytes(&bytes[..bytes.len() - 2]);
+        assert!(matches!(e, Err(ChipError::InvalidMovie(_))));
+
+        let e = Movie::from_bytes(b"SC8S");
+        assert!(matches!(e, Err(ChipError::InvalidMovie(_))));
+
+        // A movie without frames ends with the frame count and no key events
+        let empty = Movie {
+            frames: Vec::new(),
+            ..movie
+        };
+        let mut huge = empty.to_bytes();
+        huge.truncate(huge.len() - 8);
+        huge.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+        let e = Movie::from_bytes(&huge);
+        assert!(matches!(e, Err(ChipError::InvalidMovie(m)) if m.contains("limit")));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::screen::NUM_PLANES;
+use crate::{ChipError, Screen};
+
+// Every color index a pixel can hold
+const NUM_COLORS: usize = 1 << NUM_PLANES;
+
+/// The colors used to render the color indexes of the [Screen], as `0xRRGGBB` values.
+///
+/// A palette of 2 colors draws every lit pixel with the foreground, which is all
+/// CHIP-8 and Super-Chip ROMs need. XO-CHIP ROMs drawing to 2 planes need 4 colors,
+/// and 16 colors cover every combination of the 4 planes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: [u32; NUM_COLORS],
+}
+
+impl Palette {
+    /// A palette drawing every lit pixel with the foreground color.
+    pub fn new(background: u32, foreground: u32) -> Self {
+        let mut colors = [foreground; NUM_COLORS];
+        colors[0] = background;
+
+        Palette { colors }
+    }
+
+    /// A palette with one color per color index. With 4 colors only the first 2
+    /// planes are taken into account.
+    pub fn from_colors(colors: &[u32]) -> Result<Self, ChipError> {
+        match colors.len() {
+            2 => Ok(Palette::new(colors[0], colors[1])),
+            4 | NUM_COLORS => Ok(Palette {
+                colors: std::array::from_fn(|index| colors[index % colors.len()]),
+            }),
+            len => Err(ChipError::InvalidPalette(len)),
+        }
+    }
+
+    /// The `0xRRGGBB` color the color index is drawn with.
+    pub fn color(&self, index: u8) -> u32 {
+        self.colors[index as usize % NUM_COLORS]
+    }
+
+    /// Change the color the color index is drawn with.
+    pub fn set_color(&mut self, index: u8, color: u32) {
+        self.colors[index as usize % NUM_COLORS] = color;
+    }
+}
+
+/// Black and white, with the orange tones of Octo for the second plane.
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_colors(&[0x000000, 0xFFFFFF, 0xFF6600, 0x662200]).unwrap()
+    }
+}
+
+impl Screen {
+    /// The width and height of the screen once scaled.
+    pub fn scaled_size(&self, scale: usize) -> (usize, usize) {
+        (self.width * scale, self.height * scale)
+    }
+
+    /// Render the screen to the buffer as RGBA8, 4 bytes per pixel row by row, with
+    /// every pixel drawn as a square of `scale` by `scale`. The buffer needs to hold
+    /// at least the [`Screen::scaled_size`] times 4 bytes. Ready to be uploaded to
+    /// a texture.
+    pub fn render_rgba8(
+        &self,
+        palette: &Palette,
+        scale: usize,
+        buffer: &mut [u8],
+    ) -> Result<(), ChipError> {
+        let colors = palette.colors.map(|color| {
+            let [_, r, g, b] = color.to_be_bytes();
+            [r, g, b, 0xFF]
+        });
+
+        let (width, height) = self.scaled_size(scale);
+        let needed = width * height * 4;
+        if buffer.len() < needed {
+            return Err(ChipError::BufferTooSmall {
+                needed,
+                provided: buffer.len(),
+            });
+        }
+
+        self.render(&colors, scale, buffer);
+
+        Ok(())
+    }
+
+    /// Render the screen to the buffer as `0xAARRGGBB` values with an opaque alpha,
+    /// with every pixel drawn as a square of `scale` by `scale`. The buffer needs
+    /// to hold at least the [`Screen::scaled_size`] amount of values.
+    pub fn render_u32(
+        &self,
+        palette: &Palette,
+        scale: usize,
+        buffer: &mut [u32],
+    ) -> Result<(), ChipError> {
+        let colors = palette.colors.map(|color| [0xFF000000 | color]);
+
+        let (width, height) = self.scaled_size(scale);
+        if buffer.len() < width * height {
+            return Err(ChipError::BufferTooSmall {
+                needed: width * height,
+                provided: buffer.len(),
+            });
+        }
+
+        self.render(&colors, scale, buffer);
+
+        Ok(())
+    }
+
+    // Every pixel takes N values of the buffer
+    pub(crate) fn render<T: Copy, const N: usize>(
+        &self,
+        colors: &[[T; N]; NUM_COLORS],
+        scale: usize,
+        buffer: &mut [T],
+    ) {
+        let line_len = self.width * scale * N;
+        if line_len == 0 {
+            return;
+        }
+
+        for (y, row) in self.pixels().chunks(self.width).enumerate() {
+            let first_line = y * scale * line_len;
+            let line = &mut buffer[first_line..first_line + line_len];
+            for (pixel, &index) in line.chunks_exact_mut(scale * N).zip(row) {
+                let color = &colors[index as usize % NUM_COLORS];
+                for value in pixel.chunks_exact_mut(N) {
+                    value.copy_from_slice(color);
+                }
+            }
+
+            // The other lines of the scaled row are copies of the first one
+            for copy in 1..scale {
+                buffer.copy_within(
+                    first_line..first_line + line_len,
+                    first_line + copy * line_len,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes() {
+        let palette = Palette::new(0x000000, 0x00FF00);
+        assert_eq!(palette.color(0), 0x000000);
+        assert_eq!(palette.color(3), 0x00FF00);
+
+        let palette = Palette::from_colors(&[0, 1, 2, 3]).unwrap();
+        assert_eq!(palette.color(2), 2);
+        assert_eq!(palette.color(6), 2);
+
+        let e = Palette::from_colors(&[0, 1, 2]);
+        assert!(matches!(e, Err(ChipError::InvalidPalette(3))));
+    }
+
+    #[test]
+    fn render_scaled() {
+        let mut screen = Screen::default();
+        screen.set_pixel(1, 0, 0b01);
+        screen.set_pixel(0, 1, 0b11);
+        let palette = Palette::from_colors(&[0x000000, 0xFFFFFF, 0xFF6600, 0x123456]).unwrap();
+
+        assert_eq!(screen.scaled_size(2), (128, 64));
+        let mut buffer = vec![0; 128 * 64];
+        screen.render_u32(&palette, 2, &mut buffer).unwrap();
+        assert_eq!(
+            buffer[..4],
+            [0xFF000000, 0xFF000000, 0xFFFFFFFF, 0xFFFFFFFF]
+        );
+        assert_eq!(buffer[128..132], buffer[..4]);
+        assert_eq!(
+            buffer[256..260],
+            [0xFF123456, 0xFF123456, 0xFF000000, 0xFF000000]
+        );
+
+        let mut rgba = vec![0; 64 * 32 * 4];
+        screen.render_rgba8(&palette, 1, &mut rgba).unwrap();
+        assert_eq!(rgba[..8], [0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(rgba[64 * 4..64 * 4 + 4], [0x12, 0x34, 0x56, 0xFF]);
+
+        let e = screen.render_rgba8(&palette, 2, &mut rgba);
+        assert!(matches!(
+            e,
+            Err(ChipError::BufferTooSmall {
+                needed: 32768,
+                provided: 8192
+            })
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+/// How the FX55 and FX65 instructions change the index register.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// The index register is left untouched.
+    #[default]
+    Unchanged,
+    /// The index register is incremented by X.
+    ByX,
+    /// The index register is incremented by X + 1, pointing past the last register.
+    ByXPlusOne,
+}
+
+/// When FX0A, which waits for a key, is done waiting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyWait {
+    /// As soon as any key is held, even one held since before the wait started.
+    #[default]
+    Held,
+    /// When a key is pressed.
+    Press,
+    /// When a key is released after being pressed, like the COSMAC VIP.
+    Release,
+}
+
+/// The behaviours of instructions that were implemented differently by the
+/// interpreters over the years. ROMs expect the behaviour of the interpreter
+/// they were written for, so pick the preset matching the ROM.
+///
+/// The default keeps the behaviour this crate has always had, which matches no
+/// single interpreter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY and store the result in VX instead of shifting VX.
+    pub shift_uses_vy: bool,
+    /// How FX55 and FX65 change the index register.
+    pub load_store_index: IndexIncrement,
+    /// BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 set VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites are clipped at the edges of the screen instead of wrapping around.
+    pub clip_sprites: bool,
+    /// When FX0A stops waiting for a key.
+    pub key_wait: KeyWait,
+}
+
+impl Quirks {
+    /// The original interpreter for the COSMAC VIP.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        key_wait: KeyWait::Release,
+    };
+
+    /// CHIP-48 for the HP-48 calculators.
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        key_wait: KeyWait::Release,
+    };
+
+    /// Super-Chip 1.0 for the HP-48 calculators.
+    pub const SCHIP_1_0: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::ByX,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        key_wait: KeyWait::Release,
+    };
+
+    /// Super-Chip 1.1 for the HP-48 calculators.
+    pub const SCHIP_1_1: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_index: IndexIncrement::Unchanged,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        key_wait: KeyWait::Release,
+    };
+
+    /// XO-CHIP as implemented by Octo.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_index: IndexIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        key_wait: KeyWait::Release,
+    };
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+
+use crate::{Chip8, ChipError};
+
+// Keeps the latest save state in full and, for every older frame, the delta that
+// turns the following frame back into it. Most of the memory does not change
+// between frames so the deltas are mostly runs of zeroes which are compressed.
+#[derive(Default)]
+pub(crate) struct RewindBuffer {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    // The buffer of the state before the latest, reused to write the next one
+    spare: Vec<u8>,
+}
+
+impl RewindBuffer {
+    fn record(&mut self, state: Vec<u8>, depth: usize) {
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &previous));
+            self.spare = previous;
+        }
+        self.latest = Some(state);
+
+        while self.deltas.len() > depth {
+            self.deltas.pop_front();
+        }
+    }
+
+    // The state the amount of frames before the latest one, without changing the
+    // history, and how many frames back it actually is
+    fn state_before(&self, frames: usize) -> Option<(usize, Vec<u8>)> {
+        let mut state = self.latest.clone()?;
+        let frames = frames.min(self.deltas.len());
+        for delta in self.deltas.iter().rev().take(frames) {
+            state = apply_delta(&state, delta);
+        }
+
+        Some((frames, state))
+    }
+
+    // Forget the frames after the state, which becomes the latest one
+    fn truncate(&mut self, frames: usize, state: Vec<u8>) {
+        self.deltas.truncate(self.deltas.len() - frames);
+        self.latest = Some(state);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+impl Chip8 {
+    /// Go back in time to the state recorded the amount of frames before the last
+    /// recorded one. Frames are recorded at the end of every [`Chip8::tick`] when
+    /// [`Config::rewind_depth`](crate::Config::rewind_depth) is above 0.
+    ///
+    /// Returns how many frames were rewound, which is less than requested when the
+    /// history is not long enough. The history is kept when the state can't be
+    /// restored.
+    pub fn rewind(&mut self, frames: usize) -> Result<usize, ChipError> {
+        match self.rewind_buffer.state_before(frames) {
+            Some((rewound, state)) => {
+                self.load_state(&state)?;
+                self.rewind_buffer.truncate(rewound, state);
+                Ok(rewound)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// How many frames [`Chip8::rewind`] can currently go back.
+    pub fn rewind_len(&self) -> usize {
+        self.rewind_buffer.deltas.len()
+    }
+
+    pub(crate) fn record_rewind_frame(&mut self) {
+        let depth = self.config.rewind_depth;
+        if depth == 0 {
+            return;
+        }
+
+        let mut state = std::mem::take(&mut self.rewind_buffer.spare);
+        self.write_state(&mut state);
+        self.rewind_buffer.record(state, depth);
+    }
+}
+
+// The delta is the XOR of both states, with the runs of zeroes compressed. It is
+// made of the target length followed by pairs of (zero run, literal run) lengths,
+// each pair followed by the literal bytes.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor = |i: usize| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, to.len());
+
+    let mut pos = 0;
+    while pos < len {
+        let zeroes = (pos..len).take_while(|&i| xor(i) == 0).count();
+        let literals = (pos + zeroes..len).take_while(|&i| xor(i) != 0).count();
+
+        write_varint(&mut delta, zeroes);
+        write_varint(&mut delta, literals);
+        delta.extend((pos + zeroes..pos + zeroes + literals).map(xor));
+        pos += zeroes + literals;
+    }
+
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let target_len = read_varint(delta, &mut pos);
+
+    let mut to = from.to_vec();
+    to.resize(from.len().max(target_len), 0);
+
+    let mut offset = 0;
+    while pos < delta.len() {
+        offset += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for b in &delta[pos..(pos + literals)] {
+            to[offset] ^= b;
+            offset += 1;
+        }
+        pos += literals;
+    }
+
+    to.truncate(target_len);
+    to
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn delta() {
+        let from = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        let to = vec![0, 1, 9, 3, 4, 5, 6, 7, 8, 9];
+        let delta = encode_delta(&from, &to);
+        assert_eq!(apply_delta(&from, &delta), to);
+
+        let delta = encode_delta(&to, &from);
+        assert_eq!(apply_delta(&to, &delta), from);
+
+        let same = vec![0x55; 300];
+        assert!(encode_delta(&same, &same).len() < 8);
+    }
+
+    #[test]
+    fn rewind() {
+        let mut c8 = Chip8::new(Config {
+            tick_rate: 1,
+            rewind_depth: 3,
+            ..Default::default()
+        });
+        // 0x200: ADD V0, 1 ; 0x202: JP 0x200
+        c8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        for _ in 0..10 {
+            c8.tick().unwrap();
+        }
+        assert_eq!(c8.cpu.v[0], 5);
+        assert_eq!(c8.rewind_len(), 3);
+
+        assert_eq!(c8.rewind(2).unwrap(), 2);
+        assert_eq!(c8.cpu.v[0], 4);
+        assert_eq!(c8.cpu.pc, 0x200);
+        assert_eq!(c8.rewind_len(), 1);
+
+        assert_eq!(c8.rewind(5).unwrap(), 1);
+        assert_eq!(c8.cpu.v[0], 4);
+        assert_eq!(c8.cpu.pc, 0x202);
+        assert_eq!(c8.rewind(1).unwrap(), 0);
+
+        // The history continues from the rewound frame
+        c8.tick().unwrap();
+        c8.tick().unwrap();
+        assert_eq!(c8.cpu.v[0], 5);
+        assert_eq!(c8.rewind(1).unwrap(), 1);
+        assert_eq!(c8.cpu.pc, 0x200);
+        assert_eq!(c8.cpu.v[0], 4);
+    }
+
+    #[test]
+    fn failed_rewind() {
+        let mut c8 = Chip8::new(Config {
+            tick_rate: 1,
+            rewind_depth: 3,
+            ..Default::default()
+        });
+        c8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        for _ in 0..4 {
+            c8.tick().unwrap();
+        }
+
+        // The states no longer fit the memory
+        c8.memory.resize(0x10000, 0);
+        let e = c8.rewind(2);
+        assert!(matches!(e, Err(ChipError::InvalidSaveState(_))));
+        assert_eq!(c8.rewind_len(), 3);
+
+        c8.memory.truncate(0x1000);
+        assert_eq!(c8.rewind(2).unwrap(), 2);
+        assert_eq!(c8.cpu.v[0], 1);
+        assert_eq!(c8.rewind_len(), 1);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut c8 = Chip8::default();
+        c8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        c8.tick().unwrap();
+        c8.tick().unwrap();
+
+        assert_eq!(c8.rewind_len(), 0);
+        assert_eq!(c8.rewind(1).unwrap(), 0);
+        assert_eq!(c8.cpu.v[0], 10);
+    }
+}
@@ -0,0 +1,146 @@
+/// The source of random numbers for the CXNN instruction.
+///
+/// Implement it to replace the default [XorShiftRng], for example to feed the
+/// values of a recording. The state is included in save states so a restored
+/// machine keeps producing the same numbers.
+pub trait ChipRng {
+    /// Produce the next random byte. Every value from 0x00 to 0xFF must be possible.
+    fn next_u8(&mut self) -> u8;
+
+    /// Restart the sequence from the seed. Called when the machine is reset.
+    fn reseed(&mut self, seed: u64);
+
+    /// Serialize the internal state of the generator.
+    fn save(&self) -> Vec<u8>;
+
+    /// Restore a state serialized by [`ChipRng::save`]. Returns false, leaving the
+    /// generator untouched, when the state is invalid.
+    fn restore(&mut self, state: &[u8]) -> bool;
+}
+
+/// The default [ChipRng]: a small and fast xorshift64* generator.
+pub struct XorShiftRng {
+    state: u64,
+}
+
+impl XorShiftRng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = XorShiftRng { state: 0 };
+        rng.reseed(seed);
+
+        rng
+    }
+}
+
+impl ChipRng for XorShiftRng {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // Scramble the seed with SplitMix64 so similar seeds give unrelated sequences
+        // and the state is never 0, which xorshift can't leave
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        self.state = match z {
+            0 => 0x9E37_79B9_7F4A_7C15,
+            z => z,
+        };
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        match <[u8; 8]>::try_from(state) {
+            Ok(bytes) if bytes != [0; 8] => {
+                self.state = u64::from_le_bytes(bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chip8, Config};
+
+    #[test]
+    fn deterministic() {
+        let mut a = XorShiftRng::new(1234);
+        let mut b = XorShiftRng::new(1234);
+        let mut c = XorShiftRng::new(1235);
+
+        let seq_a: Vec<u8> = (0..32).map(|_| a.next_u8()).collect();
+        let seq_b: Vec<u8> = (0..32).map(|_| b.next_u8()).collect();
+        let seq_c: Vec<u8> = (0..32).map(|_| c.next_u8()).collect();
+        assert_eq!(seq_a, seq_b);
+        assert_ne!(seq_a, seq_c);
+    }
+
+    #[test]
+    fn full_range() {
+        let mut rng = XorShiftRng::new(0);
+        let mut seen = [false; 256];
+        for _ in 0..10_000 {
+            seen[rng.next_u8() as usize] = true;
+        }
+
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn chip8_seed() {
+        let config = || Config {
+            seed: Some(5),
+            ..Default::default()
+        };
+        // 0x200: RND V0, 0xFF ; 0x202: JP 0x200
+        let rom = [0xC0, 0xFF, 0x12, 0x00];
+        let mut a = Chip8::new(config());
+        let mut b = Chip8::new(config());
+        a.load_rom(&rom).unwrap();
+        b.load_rom(&rom).unwrap();
+
+        let mut values = Vec::new();
+        for _ in 0..16 {
+            a.step().unwrap();
+            b.step().unwrap();
+            assert_eq!(a.cpu.v[0], b.cpu.v[0]);
+            values.push(a.cpu.v[0]);
+        }
+        assert_eq!(a.seed(), 5);
+
+        // Resetting restarts the sequence
+        a.reset();
+        for value in values {
+            a.step().unwrap();
+            assert_eq!(a.cpu.v[0], value);
+        }
+    }
+
+    #[test]
+    fn save_restore() {
+        let mut rng = XorShiftRng::new(42);
+        rng.next_u8();
+        let state = rng.save();
+        let expected = rng.next_u8();
+
+        let mut restored = XorShiftRng::new(7);
+        assert!(restored.restore(&state));
+        assert_eq!(restored.next_u8(), expected);
+
+        assert!(!restored.restore(&[1, 2, 3]));
+        assert!(!restored.restore(&[0; 8]));
+    }
+}
@@ -1,23 +1,73 @@
 const CHIP8_SCREEN_WIDTH: usize = 64;
 const CHIP8_SCREEN_HEIGHT: usize = 32;
+const SCHIP_SCREEN_WIDTH: usize = 128;
+const SCHIP_SCREEN_HEIGHT: usize = 64;
 
-/// Represents the pixels of the 64x32 CHIP-8 display.
+/// How many bitplanes the display holds. XO-CHIP draws to 2 planes but the
+/// plane mask of FN01 can address up to 4.
+pub const NUM_PLANES: usize = 4;
+
+/// The smallest rectangle holding every pixel that changed, in pixels of the
+/// current resolution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRegion {
+    // Grows the region to hold the other one
+    fn union(self, other: DirtyRegion) -> DirtyRegion {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DirtyRegion {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+/// Represents the pixels of the CHIP-8 display.
+///
+/// The display starts in the 64x32 low resolution mode and can be switched by
+/// the Super-Chip instructions to the 128x64 high resolution mode.
 ///
-/// A pixel is drawn when equal to true. When rendering it, make sure to
+/// Every pixel is a color index where each bit represents one of the XO-CHIP
+/// bitplanes. Plain CHIP-8 and Super-Chip ROMs only draw to the first plane, so
+/// a pixel is drawn when it is different from 0. When rendering it, make sure to
 /// scale it to improve visibility in modern screens. See the
 /// examples provided for reference.
+///
+/// The screen keeps track of the region changed since [`Screen::take_dirty_region`]
+/// was last called, so frontends can redraw only that part.
 pub struct Screen {
-    screen: Vec<bool>,
-    /// How many pixels wide the display is (64 for CHIP-8)
+    screen: Vec<u8>,
+    hires: bool,
+    planes: u8,
+    dirty: Option<DirtyRegion>,
+    /// How many pixels wide the display is (64 for CHIP-8, 128 in high resolution mode)
     pub width: usize,
-    /// How many pixel high the display is (32 for CHIP-8)
+    /// How many pixel high the display is (32 for CHIP-8, 64 in high resolution mode)
     pub height: usize,
 }
 
 impl Default for Screen {
     fn default() -> Self {
         Screen {
-            screen: vec![false; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT],
+            screen: vec![0; CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT],
+            hires: false,
+            planes: 0x1,
+            // Nothing has been shown yet
+            dirty: Some(DirtyRegion {
+                x: 0,
+                y: 0,
+                width: CHIP8_SCREEN_WIDTH,
+                height: CHIP8_SCREEN_HEIGHT,
+            }),
             width: CHIP8_SCREEN_WIDTH,
             height: CHIP8_SCREEN_HEIGHT,
         }
@@ -27,27 +77,226 @@ impl Default for Screen {
 impl Screen {
     /// Clear all pixels in the screen
     pub fn clear_screen(&mut self) {
-        self.screen.fill_with(|| false);
+        self.screen.fill_with(|| 0);
+        self.mark_all_dirty();
+    }
+
+    /// Clear the pixels of the bitplanes in the mask, leaving the other planes untouched.
+    pub fn clear_planes(&mut self, planes: u8) {
+        for pixel in self.screen.iter_mut() {
+            *pixel &= !planes;
+        }
+        self.mark_all_dirty();
+    }
+
+    /// The mask of the bitplanes that drawing, clearing and scrolling affect.
+    pub fn selected_planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Select the bitplanes used by the XO-CHIP drawing instructions.
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ((1 << NUM_PLANES) - 1);
+    }
+
+    /// Whether the display is in the 128x64 high resolution mode.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switch between the 64x32 and the 128x64 resolution. The screen is cleared
+    /// when the resolution changes.
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = match hires {
+            true => (SCHIP_SCREEN_WIDTH, SCHIP_SCREEN_HEIGHT),
+            false => (CHIP8_SCREEN_WIDTH, CHIP8_SCREEN_HEIGHT),
+        };
+
+        self.hires = hires;
+        self.width = width;
+        self.height = height;
+        self.screen = vec![0; width * height];
+        self.mark_all_dirty();
+    }
+
+    /// Whether any pixel changed since the dirty region was last taken.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// The region holding every pixel changed since it was last taken, or None
+    /// when nothing changed. A change of resolution makes the whole screen dirty.
+    pub fn dirty_region(&self) -> Option<DirtyRegion> {
+        self.dirty
+    }
+
+    /// Return the dirty region and start tracking the changes anew. Call it after
+    /// redrawing the region.
+    pub fn take_dirty_region(&mut self) -> Option<DirtyRegion> {
+        self.dirty.take()
+    }
+
+    fn mark_dirty(&mut self, region: DirtyRegion) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(region),
+            None => region,
+        });
+    }
+
+    fn mark_all_dirty(&mut self) {
+        self.dirty = Some(DirtyRegion {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        });
+    }
+
+    // Marks the pixel as dirty when its color index changed
+    fn update_pixel(&mut self, x: usize, y: usize, value: u8) {
+        let pixel = &mut self.screen[x + y * self.width];
+        if *pixel != value {
+            *pixel = value;
+            self.mark_dirty(DirtyRegion {
+                x,
+                y,
+                width: 1,
+                height: 1,
+            });
+        }
+    }
+
+    /// The color indexes of all the pixels, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.screen
+    }
+
+    // Used when restoring a save state. Returns false if the pixels don't fit the resolution
+    pub(crate) fn restore(&mut self, hires: bool, planes: u8, pixels: &[u8]) -> bool {
+        self.set_hires(hires);
+        if pixels.len() != self.screen.len() {
+            return false;
+        }
+
+        self.screen.copy_from_slice(pixels);
+        self.select_planes(planes);
+        self.mark_all_dirty();
+
+        true
     }
 
-    /// Get the state of the pixel at the provided coordinates.
-    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+    /// Get the color index of the pixel at the provided coordinates. Bit N is set
+    /// when the pixel is drawn in plane N.
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
         self.screen[x + y * self.width]
     }
 
-    /// Flip the state of the pixel at the provided coordinates
-    pub fn toggle_pixel(&mut self, x: usize, y: usize) {
-        let idx = x + y * self.width;
-        self.screen[idx] = !self.screen[idx];
+    /// Flip the state of the pixel at the provided coordinates in the planes of the mask
+    pub fn toggle_pixel(&mut self, x: usize, y: usize, planes: u8) {
+        self.update_pixel(x, y, self.get_pixel(x, y) ^ planes);
+    }
+
+    /// Set the pixel at the provided coordinates in the planes of the mask
+    pub fn set_pixel(&mut self, x: usize, y: usize, planes: u8) {
+        self.update_pixel(x, y, self.get_pixel(x, y) | planes);
+    }
+
+    /// Clear the pixel at the provided coordinates in the planes of the mask
+    pub fn clear_pixel(&mut self, x: usize, y: usize, planes: u8) {
+        self.update_pixel(x, y, self.get_pixel(x, y) & !planes);
+    }
+
+    /// Move every row of the selected planes down by the amount of pixels provided.
+    /// Rows scrolled in from the top are blank.
+    pub fn scroll_down(&mut self, amount: usize) {
+        let amount = amount.min(self.height) as isize;
+        self.scroll(0, amount);
+    }
+
+    /// Move every row of the selected planes up by the amount of pixels provided.
+    /// Rows scrolled in from the bottom are blank.
+    pub fn scroll_up(&mut self, amount: usize) {
+        let amount = amount.min(self.height) as isize;
+        self.scroll(0, -amount);
+    }
+
+    /// Move every column of the selected planes left by the amount of pixels provided.
+    /// Columns scrolled in from the right are blank.
+    pub fn scroll_left(&mut self, amount: usize) {
+        let amount = amount.min(self.width) as isize;
+        self.scroll(-amount, 0);
+    }
+
+    /// Move every column of the selected planes right by the amount of pixels provided.
+    /// Columns scrolled in from the left are blank.
+    pub fn scroll_right(&mut self, amount: usize) {
+        let amount = amount.min(self.width) as isize;
+        self.scroll(amount, 0);
     }
 
-    /// Set the pixel at the provided coordinates
-    pub fn set_pixel(&mut self, x: usize, y: usize) {
-        self.screen[x + y * self.width] = true;
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let planes = self.planes;
+        let previous = self.screen.clone();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let in_bounds = (0..self.width as isize).contains(&src_x)
+                    && (0..self.height as isize).contains(&src_y);
+                let moved = match in_bounds {
+                    true => previous[src_x as usize + src_y as usize * self.width] & planes,
+                    false => 0,
+                };
+
+                let idx = x + y * self.width;
+                self.screen[idx] = (previous[idx] & !planes) | moved;
+            }
+        }
+        self.mark_all_dirty();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirty_region() {
+        let mut screen = Screen::default();
+        assert!(screen.take_dirty_region().is_some());
+        assert!(!screen.is_dirty());
+
+        screen.toggle_pixel(3, 4, 1);
+        screen.set_pixel(10, 2, 1);
+        // Already set, nothing changes
+        screen.set_pixel(3, 4, 1);
+        assert_eq!(
+            screen.take_dirty_region(),
+            Some(DirtyRegion {
+                x: 3,
+                y: 2,
+                width: 8,
+                height: 3
+            })
+        );
+        assert_eq!(screen.dirty_region(), None);
+
+        screen.clear_pixel(0, 0, 1);
+        assert!(!screen.is_dirty());
+
+        screen.scroll_down(2);
+        assert_eq!(
+            screen.take_dirty_region(),
+            Some(DirtyRegion {
+                x: 0,
+                y: 0,
+                width: 64,
+                height: 32
+            })
+        );
 
-    /// Clear the pixel at the provided coordinates
-    pub fn clear_pixel(&mut self, x: usize, y: usize) {
-        self.screen[x + y * self.width] = false;
+        screen.set_hires(true);
+        assert_eq!(screen.dirty_region().map(|r| r.width), Some(128));
     }
 }
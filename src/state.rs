@@ -0,0 +1,429 @@
+use crate::clock::Clock;
+use crate::{Chip8, ChipError, Cpu, KeyEvent, Screen};
+
+const MAGIC: &[u8; 4] = b"SC8S";
+const VERSION: u8 = 1;
+
+// Set in the serialized key events for presses, the low bits hold the key
+const KEY_DOWN: u8 = 0x10;
+
+// Little endian writer for the save state format, also used by movies
+pub(crate) struct StateWriter {
+    pub(crate) data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub(crate) fn key_events<'e>(&mut self, events: impl ExactSizeIterator<Item = &'e KeyEvent>) {
+        self.u32(events.len() as u32);
+        for event in events {
+            self.u8(match *event {
+                KeyEvent::Down(key) => KEY_DOWN | key,
+                KeyEvent::Up(key) => key,
+            });
+        }
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    // Builds the error returned when the data is invalid
+    error: fn(String) -> ChipError,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8], error: fn(String) -> ChipError) -> Self {
+        StateReader {
+            data,
+            pos: 0,
+            error,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], ChipError> {
+        if self.data.len() - self.pos < len {
+            return Err((self.error)("unexpected end of data".to_string()));
+        }
+
+        let slice = &self.data[self.pos..(self.pos + len)];
+        self.pos += len;
+
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ChipError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, ChipError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ChipError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, ChipError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, ChipError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err((self.error)("invalid flag".to_string())),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], ChipError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn key_events(&mut self) -> Result<Vec<KeyEvent>, ChipError> {
+        let mut events = Vec::new();
+        for _ in 0..self.u32()? {
+            events.push(match self.u8()? {
+                key @ 0x0..=0xF => KeyEvent::Up(key),
+                event @ 0x10..=0x1F => KeyEvent::Down(event & 0xF),
+                e => return Err((self.error)(format!("invalid key event {}", e))),
+            });
+        }
+
+        Ok(events)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], ChipError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+
+        Ok(array)
+    }
+}
+
+impl Chip8 {
+    /// Take a snapshot of the whole machine: memory, [Cpu] with the key events of the
+    /// frame, [Screen], the loaded ROM, the seed and state of the random number
+    /// generator and the time [`Chip8::advance`] carries over.
+    ///
+    /// The [Config](crate::Config) and the [Debugger](crate::Debugger) are settings of
+    /// the interpreter and are not part of the snapshot.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        self.write_state(&mut state);
+
+        state
+    }
+
+    // Replaces the contents of the buffer with the save state, reusing its allocation
+    pub(crate) fn write_state(&self, state: &mut Vec<u8>) {
+        state.clear();
+        let mut w = StateWriter {
+            data: std::mem::take(state),
+        };
+        w.data.extend_from_slice(MAGIC);
+        w.u8(VERSION);
+
+        w.bytes(&self.memory);
+        w.bytes(&self.rom);
+        w.u32(self.frame_cycles);
+
+        let cpu = &self.cpu;
+        w.data.extend_from_slice(&cpu.v);
+        w.u16(cpu.i);
+        w.u32(cpu.pc as u32);
+        w.u8(cpu.sp as u8);
+        w.u8(cpu.timer_delay);
+        w.u8(cpu.timer_sound);
+        for value in cpu.stack {
+            w.u16(value);
+        }
+        w.u16(keypad_to_bits(&cpu.keypad));
+        w.data.extend_from_slice(&cpu.rpl);
+        w.u8(cpu.halted as u8);
+        w.u8(cpu.pattern.is_some() as u8);
+        w.data.extend_from_slice(&cpu.pattern.unwrap_or_default());
+        w.u8(cpu.pitch);
+        w.u8(cpu.waiting_key.is_some() as u8);
+        w.u8(cpu.waiting_key.unwrap_or_default());
+        w.key_events(cpu.key_events.iter());
+        w.key_events(cpu.frame_key_events.iter());
+
+        w.u8(self.screen.is_hires() as u8);
+        w.u8(self.screen.selected_planes());
+        w.bytes(self.screen.pixels());
+
+        w.bytes(&self.rng.save());
+        w.u64(self.seed);
+        w.u64(self.clock.instruction_progress.to_bits());
+        w.u64(self.clock.frame_progress);
+
+        *state = w.data;
+    }
+
+    /// Restore a snapshot made by [`Chip8::save_state`]. The snapshot must have been
+    /// made with the same [Platform](crate::Platform). The machine is left untouched
+    /// when the snapshot is invalid.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), ChipError> {
+        let mut r = StateReader::new(state, ChipError::InvalidSaveState);
+
+        if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(ChipError::InvalidSaveState("not a save state".to_string()));
+        }
+        let version = r.u8()?;
+        if version != VERSION {
+            return Err(ChipError::InvalidSaveState(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let memory = r.bytes()?;
+        if memory.len() != self.memory.len() {
+            return Err(ChipError::InvalidSaveState(format!(
+                "made for a machine with {} bytes of memory",
+                memory.len()
+            )));
+        }
+        let rom = r.bytes()?;
+        let frame_cycles = r.u32()?;
+
+        let mut cpu = Cpu::default();
+        cpu.v = r.array()?;
+        cpu.i = r.u16()?;
+        cpu.pc = r.u32()? as usize;
+        cpu.sp = r.u8()? as usize;
+        cpu.timer_delay = r.u8()?;
+        cpu.timer_sound = r.u8()?;
+        for value in cpu.stack.iter_mut() {
+            *value = r.u16()?;
+        }
+        cpu.keypad = bits_to_keypad(r.u16()?);
+        cpu.rpl = r.array()?;
+        cpu.halted = r.bool()?;
+        let has_pattern = r.bool()?;
+        let pattern = r.array()?;
+        cpu.pattern = has_pattern.then_some(pattern);
+        cpu.pitch = r.u8()?;
+        let waiting = r.bool()?;
+        let waiting_key = r.u8()?;
+        cpu.waiting_key = waiting.then_some(waiting_key & 0xF);
+        cpu.key_events = r.key_events()?.into();
+        cpu.frame_key_events = r.key_events()?;
+        if cpu.sp >= cpu.stack.len() {
+            return Err(ChipError::InvalidSaveState(format!(
+                "stack pointer {} is out of bounds",
+                cpu.sp
+            )));
+        }
+
+        let mut screen = Screen::default();
+        let hires = r.bool()?;
+        let planes = r.u8()?;
+        if !screen.restore(hires, planes, r.bytes()?) {
+            return Err(ChipError::InvalidSaveState(
+                "screen does not match its resolution".to_string(),
+            ));
+        }
+
+        let rng = r.bytes()?;
+        let seed = r.u64()?;
+        let clock = Clock::restore(f64::from_bits(r.u64()?), r.u64()?)
+            .ok_or_else(|| ChipError::InvalidSaveState("invalid clock".to_string()))?;
+
+        if !r.is_empty() {
+            return Err(ChipError::InvalidSaveState(
+                "unexpected data at the end".to_string(),
+            ));
+        }
+
+        if !self.rng.restore(rng) {
+            return Err(ChipError::InvalidSaveState(
+                "invalid random number generator state".to_string(),
+            ));
+        }
+        self.memory.copy_from_slice(memory);
+        self.rom = rom.to_vec();
+        self.frame_cycles = frame_cycles;
+        self.cpu = cpu;
+        self.screen = screen;
+        self.seed = seed;
+        self.clock = clock;
+
+        Ok(())
+    }
+}
+
+pub(crate) fn keypad_to_bits(keypad: &[bool; 16]) -> u16 {
+    keypad
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (key, &pressed)| bits | ((pressed as u16) << key))
+}
+
+pub(crate) fn bits_to_keypad(bits: u16) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for (key, pressed) in keypad.iter_mut().enumerate() {
+        *pressed = bits & (1 << key) != 0;
+    }
+
+    keypad
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{Chip8, ChipError, Config, Platform, Quirks};
+
+    fn running_chip() -> Chip8 {
+        let mut c8 = Chip8::default();
+        // 0x200: CALL 0x204 ; 0x202: JP 0x202 ; 0x204: HIGH ; 0x206: LD V3, 0x42
+        // 0x208: LD F, V3 ; 0x20A: DRW V0, V0, 5 ; 0x20C: JP 0x20C
+        c8.load_rom(&[
+            0x22, 0x04, 0x12, 0x02, 0x00, 0xFF, 0x63, 0x42, 0xF3, 0x29, 0xD0, 0x05, 0x12, 0x0C,
+        ])
+        .unwrap();
+        c8.tick().unwrap();
+        c8.cpu.timer_delay = 12;
+        c8.cpu.keypad[0xB] = true;
+        c8.cpu.pattern = Some([0xF0; 16]);
+        c8.cpu.pitch = 80;
+
+        c8
+    }
+
+    #[test]
+    fn round_trip() {
+        let c8 = running_chip();
+        let state = c8.save_state();
+
+        let mut restored = Chip8::default();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.memory, c8.memory);
+        assert_eq!(restored.cpu.v, c8.cpu.v);
+        assert_eq!(restored.cpu.pc, c8.cpu.pc);
+        assert_eq!(restored.cpu.sp, 1);
+        assert_eq!(restored.cpu.stack, c8.cpu.stack);
+        assert_eq!(restored.cpu.timer_delay, 12);
+        assert!(restored.cpu.keypad[0xB]);
+        assert_eq!(restored.cpu.pattern, Some([0xF0; 16]));
+        assert_eq!(restored.cpu.pitch, 80);
+        assert!(restored.screen.is_hires());
+        assert_eq!(restored.screen.pixels(), c8.screen.pixels());
+        assert_eq!(restored.save_state(), state);
+
+        // The ROM is restored too so resetting reloads it
+        restored.reset();
+        assert_eq!(restored.memory[0x200..0x20E], c8.memory[0x200..0x20E]);
+
+        // The random number generator continues with the same sequence
+        let mut original = running_chip();
+        let mut restored = Chip8::default();
+        restored.load_state(&original.save_state()).unwrap();
+        original.load_rom(&[0xC0, 0xFF]).unwrap();
+        restored.load_rom(&[0xC0, 0xFF]).unwrap();
+        original.cpu.pc = 0x200;
+        restored.cpu.pc = 0x200;
+        original.step().unwrap();
+        restored.step().unwrap();
+        assert_eq!(original.cpu.v[0], restored.cpu.v[0]);
+    }
+
+    #[test]
+    fn mid_frame() {
+        let config = Config {
+            quirks: Quirks::COSMAC_VIP,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let mut c8 = Chip8::new(config.clone());
+        // 0x200: LD V0, K ; 0x202: JP 0x202
+        c8.load_rom(&[0xF0, 0x0A, 0x12, 0x02]).unwrap();
+        c8.advance(Duration::from_millis(5)).unwrap();
+        // A key tapped between two frames, still queued for FX0A
+        c8.key_down(0x7).unwrap();
+        c8.key_up(0x7).unwrap();
+
+        let mut restored = Chip8::new(Config {
+            seed: None,
+            ..config
+        });
+        restored.load_state(&c8.save_state()).unwrap();
+        assert_eq!(restored.seed(), 7);
+
+        // The time carried over completes a frame with the original too
+        let elapsed = Duration::from_millis(12);
+        assert_eq!(
+            restored.advance(elapsed).unwrap(),
+            c8.advance(elapsed).unwrap()
+        );
+        assert_eq!(restored.cpu.v[0], 0x7);
+        assert_eq!(c8.cpu.v[0], 0x7);
+        assert_eq!(restored.save_state(), c8.save_state());
+    }
+
+    #[test]
+    fn corrupt_state() {
+        let c8 = running_chip();
+        let state = c8.save_state();
+        let mut target = Chip8::default();
+
+        let e = target.load_state(b"nope");
+        assert!(matches!(e, Err(ChipError::InvalidSaveState(_))));
+
+        let e = target.load_state(&state[..state.len() - 1]);
+        assert!(matches!(e, Err(ChipError::InvalidSaveState(_))));
+
+        let mut bad_version = state.clone();
+        bad_version[4] = 99;
+        let e = target.load_state(&bad_version);
+        assert!(matches!(e, Err(ChipError::InvalidSaveState(_))));
+
+        let mut trailing = state.clone();
+        trailing.push(0);
+        let e = target.load_state(&trailing);
+        assert!(matches!(e, Err(ChipError::InvalidSaveState(_))));
+
+        assert_eq!(target.cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn incompatible_platform() {
+        let state = running_chip().save_state();
+        let mut xo_chip = Chip8::new(Config {
+            platform: Platform::XoChip,
+            ..Default::default()
+        });
+
+        let e = xo_chip.load_state(&state);
+        assert!(matches!(e, Err(ChipError::InvalidSaveState(_))));
+    }
+}
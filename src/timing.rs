@@ -0,0 +1,144 @@
+use crate::cpu::Opcode;
+use crate::Cpu;
+
+// The VIP runs at 1.76 MHz with 8 clock cycles per machine cycle, which leaves 3668
+// machine cycles per 60 Hz frame. The display interrupt and the DMA of the frame
+// buffer take up about 1832 of them.
+pub(crate) const VIP_CYCLES_PER_FRAME: u32 = 3668 - 1832;
+
+// Fetching and decoding every instruction
+const FETCH_CYCLES: u32 = 40;
+
+/// How long instructions take to run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction takes the same time, [`Config::tick_rate`](crate::Config::tick_rate)
+    /// of them run each frame.
+    #[default]
+    TickRate,
+    /// Every instruction takes the amount of machine cycles it took on the COSMAC VIP
+    /// and DXYN waits for the next frame, like the original interpreter waiting for
+    /// the vertical blank interrupt, then draws at its start. The tick rate is ignored.
+    CosmacVip,
+}
+
+// The machine cycles the instruction took on the VIP, given the state of the CPU
+// after running it and whether it skipped the next instruction
+pub(crate) fn vip_cycles(opcode: &Opcode, cpu: &Cpu, skipped: bool) -> u32 {
+    let skip = match skipped {
+        true => 4,
+        false => 0,
+    };
+
+    let cycles = match opcode.prefix {
+        0x0 => match opcode.hex {
+            // The screen is cleared a byte at a time
+            0x00E0 => 3078,
+            _ => 10,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10 + skip,
+        0x5 | 0x9 => 14 + skip,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22,
+        0xC => 36,
+        // Every row is shifted in place and XORed with the screen
+        0xD => 26 + opcode.n as u32 * 46,
+        0xE => 14 + skip,
+        0xF => match opcode.nn {
+            // Each digit is found by repeated subtraction
+            0x33 => {
+                let value = cpu.v[opcode.x as usize] as u32;
+                80 + 16 * (value / 100 + value / 10 % 10 + value % 10)
+            }
+            0x55 | 0x65 => 14 + 14 * (opcode.x as u32 + 1),
+            0x0A => 18,
+            0x1E | 0x29 => 16,
+            _ => 10,
+        },
+        _ => 10,
+    };
+
+    FETCH_CYCLES + cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chip8, Config, StopReason};
+
+    #[test]
+    fn cycles() {
+        let cpu = Cpu::default();
+        assert_eq!(vip_cycles(&Opcode::from(0x6012), &cpu, false), 46);
+        assert_eq!(vip_cycles(&Opcode::from(0x3012), &cpu, true), 54);
+        assert_eq!(vip_cycles(&Opcode::from(0xD015), &cpu, false), 296);
+    }
+
+    #[test]
+    fn cosmac_vip_frame() {
+        let mut c8 = Chip8::new(Config {
+            timing: Timing::CosmacVip,
+            ..Default::default()
+        });
+        // 0x200: ADD V0, 1 ; 0x202: JP 0x200
+        c8.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+
+        // Each loop takes 50 + 52 cycles, 18 of them fit in a frame
+        c8.tick().unwrap();
+        assert_eq!(c8.cpu.v[0], 18);
+        c8.tick().unwrap();
+        assert_eq!(c8.cpu.v[0], 36);
+    }
+
+    #[test]
+    fn display_wait() {
+        let mut c8 = Chip8::new(Config {
+            timing: Timing::CosmacVip,
+            ..Default::default()
+        });
+        // 0x200: ADD V0, 1 ; 0x202: DRW V1, V1, 1 ; 0x204: JP 0x200
+        c8.load_rom(&[0x70, 0x01, 0xD1, 0x11, 0x12, 0x00]).unwrap();
+        // The sprite is the first byte of the ROM, 0b0111_0000
+        c8.cpu.i = 0x200;
+
+        // The sprite waits for the next frame to be drawn
+        c8.tick().unwrap();
+        assert_eq!(c8.cpu.pc, 0x202);
+        assert_eq!(c8.cpu.v[0], 1);
+        assert_eq!(c8.screen.get_pixel(1, 0), 0);
+
+        // It is drawn first, then the loop runs up to the next one
+        c8.tick().unwrap();
+        assert_eq!(c8.cpu.pc, 0x202);
+        assert_eq!(c8.cpu.v[0], 2);
+        assert_eq!(c8.screen.get_pixel(1, 0), 1);
+        assert_eq!(c8.frame_cycles, 0);
+
+        c8.tick().unwrap();
+        assert_eq!(c8.screen.get_pixel(1, 0), 0);
+    }
+
+    #[test]
+    fn self_modifying() {
+        let mut c8 = Chip8::new(Config {
+            timing: Timing::CosmacVip,
+            ..Default::default()
+        });
+        // 0x200: LD I, 0x202 ; 0x202: LD [I], V1 writing 0xD0 0x1F over itself
+        c8.load_rom(&[0xA2, 0x02, 0xF1, 0x55]).unwrap();
+        c8.cpu.v[0] = 0xD0;
+        c8.cpu.v[1] = 0x1F;
+
+        c8.debugger.add_breakpoint(0x204);
+        assert_eq!(c8.tick().unwrap(), StopReason::Breakpoint(0x204));
+        // Costed as the FX55 that ran, not as the DXYN now in its place which would
+        // have waited for the end of the frame
+        assert_eq!(c8.memory[0x202..0x204], [0xD0, 0x1F]);
+        assert_eq!(c8.frame_cycles, 52 + 82);
+    }
+}
@@ -0,0 +1,387 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::cpu::Opcode;
+use crate::disassembler::mnemonic;
+use crate::state::{StateReader, StateWriter};
+use crate::{ChipError, Config, Platform};
+
+const MAGIC: &[u8; 4] = b"SC8T";
+const VERSION: u8 = 1;
+
+/// The registers of the [Cpu](crate::Cpu) right before an instruction ran.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub timer_delay: u8,
+    pub timer_sound: u8,
+}
+
+/// One executed instruction along with the state it changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// The registers before the instruction ran.
+    pub state: TraceState,
+    /// The address the instruction was fetched from.
+    pub pc: usize,
+    /// The first 2 bytes of the instruction.
+    pub opcode: u16,
+    /// The disassembled instruction, as shown by [disassemble](crate::disassemble).
+    pub mnemonic: String,
+    /// The V registers the instruction changed, with their new values.
+    pub registers: Vec<(u8, u8)>,
+    /// The index register after the instruction ran.
+    pub i: u16,
+    /// The bytes of memory the instruction wrote, with their new values.
+    pub writes: Vec<(usize, u8)>,
+}
+
+/// One line per instruction in the format of the [Gameboy Doctor] logs, with the
+/// registers of CHIP-8: the full state before the instruction ran, then the PC
+/// and the bytes of the opcode, in uppercase hexadecimal.
+///
+/// `V0:00 V1:05 ... VF:00 I:0300 SP:00 DT:00 ST:00 PC:0208 PCMEM:81,04`
+///
+/// Every line has the same layout so logs diff line by line against the ones of
+/// reference interpreters.
+///
+/// [Gameboy Doctor]: https://github.com/robert/gameboy-doctor
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (register, value) in self.state.v.iter().enumerate() {
+            write!(f, "V{:X}:{:02X} ", register, value)?;
+        }
+        let [high, low] = self.opcode.to_be_bytes();
+        write!(
+            f,
+            "I:{:04X} SP:{:02X} DT:{:02X} ST:{:02X} PC:{:04X} PCMEM:{:02X},{:02X}",
+            self.state.i,
+            self.state.sp,
+            self.state.timer_delay,
+            self.state.timer_sound,
+            self.pc,
+            high,
+            low
+        )
+    }
+}
+
+/// Receives a [TraceRecord] for every instruction executed by the [Cpu](crate::Cpu)
+/// once set with [`Cpu::set_tracer`](crate::Cpu::set_tracer).
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+}
+
+/// Lets the caller keep a handle on a sink while the [Cpu](crate::Cpu) owns it.
+impl<T: TraceSink> TraceSink for Rc<RefCell<T>> {
+    fn record(&mut self, record: &TraceRecord) {
+        self.borrow_mut().record(record);
+    }
+}
+
+/// Keeps the latest records in memory, dropping the oldest ones once full.
+pub struct RingBufferSink {
+    capacity: usize,
+    records: VecDeque<TraceRecord>,
+}
+
+impl RingBufferSink {
+    pub fn new(capacity: usize) -> Self {
+        RingBufferSink {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// The kept records, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.records.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+impl TraceSink for RingBufferSink {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record.clone());
+    }
+}
+
+/// Writes every record as a line of text in the format of the [TraceRecord]
+/// [Display](fmt::Display) implementation.
+pub struct TextSink<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        TextSink {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Flush and return the writer, or the first error met while writing. Nothing
+    /// more is written after an error.
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TextSink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = writeln!(self.writer, "{}", record).err();
+        }
+    }
+}
+
+/// Writes the records in a compact binary form, read back with [read_binary_trace].
+///
+/// The log starts with the `SC8T` magic and a version byte, then every record is
+/// stored little endian as the registers before the instruction (V0 to VF, I as a u16,
+/// SP, DT and ST), the PC (u16), the opcode (u16), I (u16), the amount of changed
+/// registers (u8) followed by (register, value) byte pairs, and the amount of written
+/// bytes (u16) followed by (address u16, value u8) entries. The mnemonic is left out.
+pub struct BinarySink<W: Write> {
+    writer: W,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> BinarySink<W> {
+    pub fn new(writer: W) -> Self {
+        BinarySink {
+            writer,
+            started: false,
+            error: None,
+        }
+    }
+
+    /// Flush and return the writer, or the first error met while writing. Nothing
+    /// more is written after an error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.error.is_none() && !self.started {
+            self.error = self.write_header().err();
+        }
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.started = true;
+        self.writer.write_all(MAGIC)?;
+        self.writer.write_all(&[VERSION])
+    }
+
+    fn write_record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if !self.started {
+            self.write_header()?;
+        }
+
+        let mut w = StateWriter {
+            data: Vec::with_capacity(16),
+        };
+        record.state.v.iter().for_each(|&value| w.u8(value));
+        w.u16(record.state.i);
+        w.u8(record.state.sp);
+        w.u8(record.state.timer_delay);
+        w.u8(record.state.timer_sound);
+        w.u16(record.pc as u16);
+        w.u16(record.opcode);
+        w.u16(record.i);
+        w.u8(record.registers.len() as u8);
+        for &(register, value) in &record.registers {
+            w.u8(register);
+            w.u8(value);
+        }
+        w.u16(record.writes.len() as u16);
+        for &(address, value) in &record.writes {
+            w.u16(address as u16);
+            w.u8(value);
+        }
+
+        self.writer.write_all(&w.data)
+    }
+}
+
+impl<W: Write> TraceSink for BinarySink<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = self.write_record(record).err();
+        }
+    }
+}
+
+/// Parse a log written by [BinarySink]. The mnemonics are disassembled again with
+/// the platform and quirks of the [Config] the log was recorded with.
+pub fn read_binary_trace(data: &[u8], config: &Config) -> Result<Vec<TraceRecord>, ChipError> {
+    let mut r = StateReader::new(data, ChipError::InvalidTrace);
+    if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(ChipError::InvalidTrace("not a trace".to_string()));
+    }
+    let version = r.u8()?;
+    if version != VERSION {
+        return Err(ChipError::InvalidTrace(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+
+    let mut records = Vec::new();
+    while !r.is_empty() {
+        let mut state = TraceState::default();
+        state.v.copy_from_slice(r.take(16)?);
+        state.i = r.u16()?;
+        state.sp = r.u8()?;
+        state.timer_delay = r.u8()?;
+        state.timer_sound = r.u8()?;
+        let pc = r.u16()? as usize;
+        let opcode = r.u16()?;
+        let i = r.u16()?;
+
+        let mut registers = Vec::new();
+        for _ in 0..r.u8()? {
+            registers.push((r.u8()?, r.u8()?));
+        }
+        let mut writes = Vec::new();
+        for _ in 0..r.u16()? {
+            writes.push((r.u16()? as usize, r.u8()?));
+        }
+
+        records.push(TraceRecord {
+            state,
+            pc,
+            opcode,
+            mnemonic: trace_mnemonic(opcode, i, config),
+            registers,
+            i,
+            writes,
+        });
+    }
+
+    Ok(records)
+}
+
+// The long load is the only instruction not fully described by its opcode, but its
+// operand is what ends up in I
+fn trace_mnemonic(opcode: u16, i: u16, config: &Config) -> String {
+    match (opcode, config.platform) {
+        (0xF000, Platform::XoChip) => format!("LD I, {:#06X}", i),
+        _ => mnemonic(&Opcode::from(opcode), config),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+
+    fn traced_run(sink: impl TraceSink + 'static) -> Chip8 {
+        let mut c8 = Chip8::new(Config {
+            platform: Platform::XoChip,
+            ..Default::default()
+        });
+        c8.load_rom(&[
+            0x60, 0x05, // 0x200: LD V0, 0x05
+            0xF0, 0x00, 0x03, 0x00, // 0x202: LD I, 0x0300
+            0xF0, 0x33, // 0x206: LD B, V0
+            0x81, 0x04, // 0x208: ADD V1, V0
+        ])
+        .unwrap();
+        c8.cpu.set_tracer(Some(Box::new(sink)));
+        for _ in 0..4 {
+            c8.step().unwrap();
+        }
+
+        c8
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let sink = Rc::new(RefCell::new(RingBufferSink::new(3)));
+        traced_run(sink.clone());
+
+        let sink = sink.borrow();
+        let records: Vec<&TraceRecord> = sink.records().collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].pc, 0x202);
+        assert_eq!(records[0].mnemonic, "LD I, 0x0300");
+        assert_eq!(records[1].writes, vec![(0x300, 0), (0x301, 0), (0x302, 5)]);
+        assert_eq!(records[2].registers, vec![(1, 5)]);
+        assert_eq!(
+            records[2].to_string(),
+            "V0:05 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 \
+             V8:00 V9:00 VA:00 VB:00 VC:00 VD:00 VE:00 VF:00 \
+             I:0300 SP:00 DT:00 ST:00 PC:0208 PCMEM:81,04"
+        );
+    }
+
+    #[test]
+    fn text() {
+        let sink = Rc::new(RefCell::new(TextSink::new(Vec::new())));
+        let mut c8 = traced_run(sink.clone());
+        assert!(c8.cpu.take_tracer().is_some());
+        assert!(c8.cpu.take_tracer().is_none());
+
+        let sink = Rc::try_unwrap(sink).ok().unwrap().into_inner();
+        let text = String::from_utf8(sink.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 \
+             V8:00 V9:00 VA:00 VB:00 VC:00 VD:00 VE:00 VF:00 \
+             I:0000 SP:00 DT:00 ST:00 PC:0200 PCMEM:60,05"
+        );
+        // Every line has the same length
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let ring = Rc::new(RefCell::new(RingBufferSink::new(16)));
+        let binary = Rc::new(RefCell::new(BinarySink::new(Vec::new())));
+
+        struct Both(
+            Rc<RefCell<RingBufferSink>>,
+            Rc<RefCell<BinarySink<Vec<u8>>>>,
+        );
+        impl TraceSink for Both {
+            fn record(&mut self, record: &TraceRecord) {
+                self.0.record(record);
+                self.1.record(record);
+            }
+        }
+        let mut c8 = traced_run(Both(ring.clone(), binary.clone()));
+        drop(c8.cpu.take_tracer());
+
+        let binary = Rc::try_unwrap(binary).ok().unwrap().into_inner();
+        let data = binary.finish().unwrap();
+        let records = read_binary_trace(&data, &c8.config).unwrap();
+        let expected: Vec<TraceRecord> = ring.borrow().records().cloned().collect();
+        assert_eq!(records, expected);
+
+        let e = read_binary_trace(&data[..data.len() - 1], &c8.config);
+        assert!(matches!(e, Err(ChipError::InvalidTrace(_))));
+    }
+}